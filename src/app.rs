@@ -30,9 +30,9 @@ pub struct App {
 
     /// read/process named sql file, by default is going to read input from stdout
     #[arg(short, long)]
-    init: Option<String>,
+    pub(crate) init: Option<String>,
 
-    /// run "COMMAND" before reading stdin
+    /// run "COMMAND" and exit instead of reading stdin
     command: Option<String>,
 
     /// turn headers on or off
@@ -47,9 +47,49 @@ pub struct App {
     #[arg(short, long)]
     echo: bool,
 
+    /// stop after hitting an error
+    #[arg(long)]
+    bail: bool,
+
+    /// wait up to MS milliseconds for locks held by other connections
+    #[arg(long, value_name = "MS")]
+    timeout: Option<u32>,
+
+    /// program that opens the output of `.once -x` and `.excel`, e.g. "libreoffice --calc"
+    #[arg(long, value_name = "CMD")]
+    launcher: Option<String>,
+
     /// replace null values with something else
     #[arg(long = "null-value")]
     null_value: Option<String>,
+
+    /// load an extension library before reading input, may be given more than once
+    #[arg(long, value_name = "FILE")]
+    pub(crate) load: Vec<String>,
+
+    /// force batch mode: no prompt and no history, even when stdin is a terminal
+    #[arg(long, overrides_with = "interactive")]
+    pub(crate) batch: bool,
+
+    /// force interactive mode, even when stdin is not a terminal
+    #[arg(long, overrides_with = "batch")]
+    pub(crate) interactive: bool,
+
+    /// run ".archive ARGS" on the database and exit, e.g. `-A cvf archive.db file...`
+    #[arg(short = 'A', num_args = 1.., allow_hyphen_values = true, value_name = "ARGS")]
+    pub(crate) archive: Option<Vec<String>>,
+}
+
+impl App {
+    /// parses the command line. `-batch` and `-interactive` are also accepted with a single
+    /// dash, the way the `sqlite3` shell spells them
+    pub fn parse_args() -> Self {
+        Self::parse_from(std::env::args_os().map(|arg| match arg.to_str() {
+            Some("-batch") => "--batch".into(),
+            Some("-interactive") => "--interactive".into(),
+            _ => arg,
+        }))
+    }
 }
 
 impl From<App> for Context {
@@ -64,16 +104,18 @@ impl From<App> for Context {
             ctx.set_with_header();
         }
 
+        ctx.bail = value.bail;
+
+        if let Some(ms) = value.timeout {
+            ctx.timeout.ms = ms;
+        }
+
         ctx.set_mode(value.mode);
 
         if let Some(conn) = value.filename {
             ctx.set_conn(conn);
         }
 
-        if let Some(output_file) = value.init {
-            ctx.set_output(output_file);
-        }
-
         if let Some(cmd) = value.command {
             ctx.set_command(cmd);
         }
@@ -82,6 +124,10 @@ impl From<App> for Context {
             ctx.set_null_value(nv);
         }
 
+        if let Some(launcher) = value.launcher {
+            ctx.launcher = launcher;
+        }
+
         ctx
     }
 }
@@ -0,0 +1,552 @@
+use anyhow::{Context as _, bail};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{Connection, OptionalExtension};
+use std::fs::{self, File, FileTimes};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SQLAR_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS sqlar(
+  name TEXT PRIMARY KEY,  -- name of the file
+  mode INT,               -- access permissions
+  mtime INT,              -- last modification time
+  sz INT,                 -- original file size
+  data BLOB               -- compressed content
+)";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+#[cfg(not(unix))]
+const S_IFREG: u32 = 0o100000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveCommand {
+    Create,
+    Extract,
+    List,
+    Update,
+    Insert,
+}
+
+#[derive(Debug)]
+pub struct ArchiveOptions {
+    pub command: ArchiveCommand,
+    pub verbose: bool,
+    pub file: Option<String>,
+    pub directory: Option<String>,
+    pub files: Vec<String>,
+}
+
+impl ArchiveOptions {
+    // like the `sqlite3` shell, this accepts long options, clustered short options (`-cvf`)
+    // and the `tar` style without a dash (`cvf`)
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let args: Vec<&str> = args.iter().copied().filter(|arg| !arg.is_empty()).collect();
+        let mut command = None;
+        let mut opts = Self {
+            command: ArchiveCommand::List,
+            verbose: false,
+            file: None,
+            directory: None,
+            files: Vec::new(),
+        };
+
+        let mut idx = 0;
+        // the value of an option is the rest of its cluster or the next argument
+        let take_value = |name: &str, inline: &str, idx: &mut usize| {
+            if !inline.is_empty() {
+                return Ok(inline.to_string());
+            }
+            *idx += 1;
+            args.get(*idx)
+                .map(|value| value.to_string())
+                .ok_or_else(|| format!("option {} requires an argument", name))
+        };
+
+        if let Some(first) = args.first().filter(|first| !first.starts_with('-')) {
+            // every letter is an option and their values follow in the same order
+            let mut values = 1;
+            for short in first.chars() {
+                if opts.apply_short(short, &mut command)? {
+                    let value = args
+                        .get(values)
+                        .ok_or_else(|| format!("option -{} requires an argument", short))?;
+                    opts.set_value(short, value.to_string());
+                    values += 1;
+                }
+            }
+            idx = values;
+        } else {
+            while let Some(arg) = args.get(idx) {
+                if let Some(long) = arg.strip_prefix("--") {
+                    let (name, inline) = long.split_once('=').unwrap_or((long, ""));
+                    let short = match name {
+                        "create" => 'c',
+                        "extract" => 'x',
+                        "list" => 't',
+                        "update" => 'u',
+                        "insert" => 'i',
+                        "verbose" => 'v',
+                        "file" => 'f',
+                        "directory" => 'C',
+                        _ => return Err(format!("unknown option: {}", arg)),
+                    };
+                    if opts.apply_short(short, &mut command)? {
+                        let value = take_value(arg, inline, &mut idx)?;
+                        opts.set_value(short, value);
+                    }
+                } else if let Some(cluster) = arg.strip_prefix('-') {
+                    for (pos, short) in cluster.char_indices() {
+                        if opts.apply_short(short, &mut command)? {
+                            let inline = &cluster[pos + short.len_utf8()..];
+                            let value = take_value(&format!("-{}", short), inline, &mut idx)?;
+                            opts.set_value(short, value);
+                            break;
+                        }
+                    }
+                } else {
+                    break;
+                }
+                idx += 1;
+            }
+        }
+
+        opts.files = args[idx.min(args.len())..]
+            .iter()
+            .map(|file| file.to_string())
+            .collect();
+        opts.command = command.ok_or("required argument missing, specify -c, -x, -t, -u or -i")?;
+
+        Ok(opts)
+    }
+
+    fn apply_short(
+        &mut self,
+        short: char,
+        command: &mut Option<ArchiveCommand>,
+    ) -> Result<bool, String> {
+        let new_command = match short {
+            'c' => ArchiveCommand::Create,
+            'x' => ArchiveCommand::Extract,
+            't' => ArchiveCommand::List,
+            'u' => ArchiveCommand::Update,
+            'i' => ArchiveCommand::Insert,
+            'v' => {
+                self.verbose = true;
+                return Ok(false);
+            }
+            'f' | 'C' => return Ok(true),
+            _ => return Err(format!("unknown option: -{}", short)),
+        };
+
+        if command.is_some_and(|command| command != new_command) {
+            return Err("multiple command options".to_string());
+        }
+        *command = Some(new_command);
+        Ok(false)
+    }
+
+    fn set_value(&mut self, short: char, value: String) {
+        match short {
+            'f' => self.file = Some(value),
+            _ => self.directory = Some(value),
+        }
+    }
+}
+
+pub fn run_archive(
+    conn: &Connection,
+    opts: &ArchiveOptions,
+    cwd: &Path,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let archive_conn;
+    let conn = match &opts.file {
+        Some(file) => {
+            let path = cwd.join(file);
+            // reading an archive shouldn't leave an empty database file behind
+            let reads_only = matches!(opts.command, ArchiveCommand::Extract | ArchiveCommand::List);
+            if reads_only && !path.exists() {
+                bail!("cannot open archive \"{}\"", file);
+            }
+            archive_conn = Connection::open(path)?;
+            &archive_conn
+        }
+        None => conn,
+    };
+    let directory = match &opts.directory {
+        Some(directory) => cwd.join(directory),
+        None => cwd.to_path_buf(),
+    };
+
+    let result = match opts.command {
+        ArchiveCommand::Create | ArchiveCommand::Update | ArchiveCommand::Insert => {
+            add_files(conn, opts, &directory, writer)
+        }
+        ArchiveCommand::Extract => extract_files(conn, opts, &directory, writer),
+        ArchiveCommand::List => list_files(conn, opts, writer),
+    };
+    writer.flush().expect("unable to flush");
+
+    result
+}
+
+fn add_files(
+    conn: &Connection,
+    opts: &ArchiveOptions,
+    directory: &Path,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    if opts.files.is_empty() && opts.command != ArchiveCommand::Create {
+        bail!("no files to add to the archive");
+    }
+
+    // if anything fails midway the transaction is rolled back when it is dropped
+    let tx = conn.unchecked_transaction()?;
+    if opts.command == ArchiveCommand::Create {
+        tx.execute("DROP TABLE IF EXISTS sqlar", ())?;
+    }
+    tx.execute(SQLAR_SCHEMA, ())?;
+
+    let mut insert_stmt =
+        tx.prepare("REPLACE INTO sqlar(name, mode, mtime, sz, data) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+    let mut mtime_stmt = tx.prepare("SELECT mtime FROM sqlar WHERE name = ?1")?;
+
+    let mut pending: Vec<String> = opts
+        .files
+        .iter()
+        .map(|file| file.trim_end_matches('/').to_string())
+        .rev()
+        .collect();
+    while let Some(name) = pending.pop() {
+        let path = directory.join(&name);
+        let meta =
+            fs::symlink_metadata(&path).with_context(|| format!("cannot stat \"{}\"", name))?;
+        let mode = file_mode(&meta);
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+
+        if meta.is_dir() {
+            let mut children = fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            children.sort();
+            pending.extend(
+                children
+                    .into_iter()
+                    .rev()
+                    .map(|child| format!("{}/{}", name, child)),
+            );
+        }
+
+        // `--update` skips the files that didn't change since they were archived
+        if opts.command == ArchiveCommand::Update {
+            let archived = mtime_stmt
+                .query_row([&name], |row| row.get::<_, i64>(0))
+                .optional()?;
+            if archived == Some(mtime) {
+                continue;
+            }
+        }
+
+        // the `sqlite3` shell stores the target of a symbolic link as text
+        let (sz, data) = if meta.is_dir() {
+            (0, Value::Null)
+        } else if meta.is_symlink() {
+            let target = fs::read_link(&path)?;
+            (-1, Value::Text(target.to_string_lossy().to_string()))
+        } else {
+            let content = fs::read(&path).with_context(|| format!("cannot read \"{}\"", name))?;
+            (content.len() as i64, Value::Blob(compress(content)?))
+        };
+
+        insert_stmt.execute((&name, mode, mtime, sz, data))?;
+        if opts.verbose {
+            let _ = writeln!(writer, "{}", name);
+        }
+    }
+
+    drop(insert_stmt);
+    drop(mtime_stmt);
+    tx.commit()?;
+
+    Ok(())
+}
+
+// entries whose name would leave `directory` are skipped and reported as an error
+fn extract_files(
+    conn: &Connection,
+    opts: &ArchiveOptions,
+    directory: &Path,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let entries = archived_entries(conn, opts)?;
+    // directories get their permissions and modification time last, a read-only directory
+    // couldn't take its children and extracting into a directory changes its mtime
+    let mut directories: Vec<(PathBuf, u32, i64)> = Vec::new();
+    // symlinks are created last, so that no other entry is written through one of them
+    let mut symlinks: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+
+    for (name, mode, mtime, sz, data) in entries {
+        let path = directory.join(&name);
+        let below_symlink = symlinks.iter().any(|(link, _)| path.starts_with(link));
+        if !is_safe_name(&name) || below_symlink || has_symlink_parent(directory, &name) {
+            skipped.push(name);
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // a symlink left by an earlier extraction is replaced instead of written through
+        if fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            fs::remove_file(&path)?;
+        }
+
+        if mode & S_IFMT == S_IFDIR {
+            fs::create_dir_all(&path)?;
+            directories.push((path, mode, mtime));
+        } else if mode & S_IFMT == S_IFLNK {
+            symlinks.push((path, data.unwrap_or_default()));
+        } else {
+            let content = uncompress(data.unwrap_or_default(), sz)
+                .with_context(|| format!("cannot uncompress \"{}\"", name))?;
+            let mut file = File::create(&path)?;
+            file.write_all(&content)?;
+            file.set_times(FileTimes::new().set_modified(unix_time(mtime)))?;
+            set_permissions(&path, mode)?;
+        }
+
+        if opts.verbose {
+            let _ = writeln!(writer, "{}", name);
+        }
+    }
+
+    #[cfg(unix)]
+    for (path, target) in symlinks {
+        let _ = fs::remove_file(&path);
+        std::os::unix::fs::symlink(String::from_utf8_lossy(&target).to_string(), &path)?;
+    }
+    #[cfg(not(unix))]
+    drop(symlinks);
+
+    // children come after their parent, so going backwards handles them first
+    for (path, mode, mtime) in directories.into_iter().rev() {
+        if let Ok(dir) = File::open(&path) {
+            let _ = dir.set_times(FileTimes::new().set_modified(unix_time(mtime)));
+        }
+        set_permissions(&path, mode)?;
+    }
+
+    if !skipped.is_empty() {
+        bail!(
+            "refusing to extract entries outside of \"{}\": {}",
+            directory.display(),
+            skipped.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+fn is_safe_name(name: &str) -> bool {
+    let path = Path::new(name);
+    !name.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+// writing through a symlinked directory could end up anywhere
+fn has_symlink_parent(directory: &Path, name: &str) -> bool {
+    let mut path = directory.to_path_buf();
+    let parents = Path::new(name)
+        .parent()
+        .into_iter()
+        .flat_map(Path::components);
+    for component in parents {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => return true,
+            Ok(_) => {}
+            // nothing below a missing directory can exist yet
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+fn list_files(
+    conn: &Connection,
+    opts: &ArchiveOptions,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    for (name, mode, mtime, sz, _) in archived_entries(conn, opts)? {
+        if opts.verbose {
+            let mtime: String =
+                conn.query_row("SELECT datetime(?1, 'unixepoch')", [mtime], |row| {
+                    row.get(0)
+                })?;
+            let _ = writeln!(writer, "{} {:>10}  {}  {}", ls_mode(mode), sz, mtime, name);
+        } else {
+            let _ = writeln!(writer, "{}", name);
+        }
+    }
+
+    Ok(())
+}
+
+type ArchivedEntry = (String, u32, i64, i64, Option<Vec<u8>>);
+
+fn archived_entries(
+    conn: &Connection,
+    opts: &ArchiveOptions,
+) -> anyhow::Result<Vec<ArchivedEntry>> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_schema WHERE type = 'table' AND name = 'sqlar'",
+            (),
+            |_| Ok(()),
+        )
+        .optional()?;
+    if exists.is_none() {
+        bail!("no sqlar table found in the archive");
+    }
+
+    let mut stmt = conn.prepare("SELECT name, mode, mtime, sz, data FROM sqlar ORDER BY name")?;
+    let entries = stmt
+        .query_map((), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?.unwrap_or(0) as u32,
+                row.get::<_, Option<i64>>(2)?.unwrap_or(0),
+                row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+                match row.get_ref(4)? {
+                    ValueRef::Text(bytes) | ValueRef::Blob(bytes) => Some(bytes.to_vec()),
+                    _ => None,
+                },
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut unmatched: Vec<&String> = opts.files.iter().collect();
+    let entries: Vec<ArchivedEntry> = entries
+        .into_iter()
+        .filter(|(name, ..)| {
+            if opts.files.is_empty() {
+                return true;
+            }
+            let matches = |file: &&String| {
+                let file = file.trim_end_matches('/');
+                name == file || name.starts_with(&format!("{}/", file))
+            };
+            unmatched.retain(|file| !matches(file));
+            opts.files.iter().any(|file| matches(&file))
+        })
+        .collect();
+
+    if let Some(file) = unmatched.first() {
+        bail!("not found in archive: {}", file);
+    }
+
+    Ok(entries)
+}
+
+// like `sqlar_compress()`, the content is only stored compressed when that makes it smaller
+fn compress(content: Vec<u8>) -> std::io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&content)?;
+    let compressed = encoder.finish()?;
+
+    Ok(if compressed.len() < content.len() {
+        compressed
+    } else {
+        content
+    })
+}
+
+// data whose length is the original size was stored as is
+fn uncompress(data: Vec<u8>, sz: i64) -> std::io::Result<Vec<u8>> {
+    if sz <= 0 || sz as usize == data.len() {
+        return Ok(data);
+    }
+
+    // the stored size can't be trusted, one byte more than it is enough to tell it was wrong
+    let mut content = Vec::new();
+    ZlibDecoder::new(data.as_slice())
+        .take(sz as u64 + 1)
+        .read_to_end(&mut content)?;
+    if content.len() as u64 != sz as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "the uncompressed data doesn't match its stored size of {} bytes",
+                sz
+            ),
+        ));
+    }
+    Ok(content)
+}
+
+fn unix_time(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    meta.mode()
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u32 {
+    if meta.is_dir() {
+        S_IFDIR | 0o755
+    } else if meta.is_symlink() {
+        S_IFLNK | 0o777
+    } else if meta.permissions().readonly() {
+        S_IFREG | 0o444
+    } else {
+        S_IFREG | 0o644
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+fn ls_mode(mode: u32) -> String {
+    let kind = match mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        _ => '-',
+    };
+    let permissions = (0..9).map(|bit| {
+        let granted = mode & (0o400 >> bit) != 0;
+        match (granted, bit % 3) {
+            (false, _) => '-',
+            (true, 0) => 'r',
+            (true, 1) => 'w',
+            (true, _) => 'x',
+        }
+    });
+
+    std::iter::once(kind).chain(permissions).collect()
+}
@@ -0,0 +1,90 @@
+use crate::interrupt;
+use rusqlite::Connection;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// the same steps SQLite's own busy handler takes
+const DELAYS_MS: [u64; 12] = [1, 2, 5, 10, 15, 20, 25, 25, 25, 50, 50, 100];
+
+static BUSY: Mutex<Busy> = Mutex::new(Busy {
+    timeout: Duration::ZERO,
+    wait_started: None,
+    waited: Duration::ZERO,
+});
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeoutOptions {
+    pub ms: u32,
+    pub verbose: bool,
+}
+
+impl TimeoutOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut ms = None;
+        let mut verbose = false;
+
+        for &arg in args.iter().filter(|arg| !arg.is_empty()) {
+            match arg {
+                "--verbose" | "-v" => verbose = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => {
+                    let value = arg
+                        .parse()
+                        .map_err(|_| format!("invalid value for MS: {}", arg))?;
+                    ms = Some(value);
+                }
+            }
+        }
+
+        let ms = ms.ok_or("Usage: .timeout MS ?--verbose?")?;
+        Ok(Self { ms, verbose })
+    }
+}
+
+struct Busy {
+    timeout: Duration,
+    wait_started: Option<Instant>,
+    waited: Duration,
+}
+
+// this is needed whenever the connection is replaced
+pub fn install(conn: &Connection, opts: TimeoutOptions) {
+    BUSY.lock().unwrap().timeout = Duration::from_millis(opts.ms.into());
+    let _ = if opts.ms > 0 {
+        conn.busy_handler(Some(on_busy))
+    } else {
+        conn.busy_handler(None)
+    };
+}
+
+pub fn statement_started() {
+    let mut busy = BUSY.lock().unwrap();
+    busy.wait_started = None;
+    busy.waited = Duration::ZERO;
+}
+
+pub fn waited() -> Duration {
+    BUSY.lock().unwrap().waited
+}
+
+fn on_busy(count: i32) -> bool {
+    let mut busy = BUSY.lock().unwrap();
+    let now = Instant::now();
+    // every lock the statement needs starts a new count
+    if count == 0 {
+        busy.wait_started = Some(now);
+    }
+    let elapsed = now - *busy.wait_started.get_or_insert(now);
+    if elapsed >= busy.timeout || interrupt::seen_interrupt() {
+        return false;
+    }
+
+    let step = usize::try_from(count).unwrap_or(0).min(DELAYS_MS.len() - 1);
+    let delay = Duration::from_millis(DELAYS_MS[step]).min(busy.timeout - elapsed);
+    busy.waited += delay;
+    drop(busy);
+
+    sleep(delay);
+    true
+}
@@ -0,0 +1,216 @@
+use crate::config::ColumnarOptions;
+use prettytable::format::Alignment;
+use unicode_width::UnicodeWidthStr;
+
+const MIN_FITTED_WIDTH: usize = 8;
+const COLUMN_OVERHEAD: usize = 3;
+
+pub struct ColumnLayout {
+    pub widths: Vec<usize>,
+    pub alignments: Vec<Alignment>,
+    pub fixed: Vec<bool>,
+}
+
+impl ColumnLayout {
+    // a width from `.width` always wins, the other columns are as wide as their longest line,
+    // at most `--wrap` wide, and shrink until the table fits into `max_width`
+    pub fn new(
+        title: &[String],
+        data: &[Vec<String>],
+        opts: &ColumnarOptions,
+        max_width: Option<usize>,
+    ) -> Self {
+        let col_count = title.len();
+        let mut layout = Self {
+            widths: Vec::with_capacity(col_count),
+            alignments: Vec::with_capacity(col_count),
+            fixed: Vec::with_capacity(col_count),
+        };
+
+        for (col_idx, name) in title.iter().enumerate() {
+            let requested = opts.widths.get(col_idx).copied().unwrap_or(0);
+            if requested != 0 {
+                layout.widths.push(requested.unsigned_abs() as usize);
+                layout.fixed.push(true);
+            } else {
+                let natural = data
+                    .iter()
+                    .filter_map(|row| row.get(col_idx))
+                    .chain(std::iter::once(name))
+                    .map(|cell| text_width(cell))
+                    .max()
+                    .unwrap_or(0);
+                let width = if opts.wrap > 0 {
+                    natural.min(opts.wrap)
+                } else {
+                    natural
+                };
+                layout.widths.push(width.max(1));
+                layout.fixed.push(false);
+            }
+
+            let alignment = if requested < 0 {
+                Alignment::RIGHT
+            } else {
+                Alignment::LEFT
+            };
+            layout.alignments.push(alignment);
+        }
+
+        if let Some(max_width) = max_width {
+            layout.shrink_to(max_width);
+        }
+
+        layout
+    }
+
+    pub fn apply(
+        &self,
+        title: Vec<String>,
+        data: Vec<Vec<String>>,
+        word_wrap: bool,
+        truncate: bool,
+    ) -> (Vec<String>, Vec<Vec<String>>) {
+        let fit = |col_idx: usize, cell: &str| {
+            let width = self.widths.get(col_idx).copied().unwrap_or(usize::MAX);
+            if truncate {
+                truncate_text(cell, width)
+            } else {
+                wrap_text(cell, width, word_wrap)
+            }
+        };
+
+        let title = title
+            .iter()
+            .enumerate()
+            .map(|(col_idx, name)| {
+                let name = fit(col_idx, name);
+                if self.fixed.get(col_idx).copied().unwrap_or(false) {
+                    center_text(&name, self.widths[col_idx])
+                } else {
+                    name
+                }
+            })
+            .collect();
+        let data = data
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(col_idx, cell)| fit(col_idx, cell))
+                    .collect()
+            })
+            .collect();
+
+        (title, data)
+    }
+
+    fn shrink_to(&mut self, max_width: usize) {
+        let overhead = COLUMN_OVERHEAD * self.widths.len() + 1;
+        let mut total: usize = self.widths.iter().sum::<usize>() + overhead;
+
+        while total > max_width {
+            let widest = (0..self.widths.len())
+                .filter(|&col_idx| !self.fixed[col_idx])
+                .max_by_key(|&col_idx| self.widths[col_idx]);
+            match widest {
+                Some(col_idx) if self.widths[col_idx] > MIN_FITTED_WIDTH => {
+                    self.widths[col_idx] -= 1;
+                    total -= 1;
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+pub fn text_width(text: &str) -> usize {
+    text.lines().map(UnicodeWidthStr::width).max().unwrap_or(0)
+}
+
+pub fn wrap_text(text: &str, width: usize, word_wrap: bool) -> String {
+    let mut wrapped: Vec<&str> = Vec::new();
+
+    for line in text.lines() {
+        let mut rest = line;
+        let first = wrapped.len();
+        while rest.width() > width {
+            let hard_end = prefix_len(rest, width);
+            let end = if word_wrap {
+                rest[..hard_end]
+                    .rfind(' ')
+                    .filter(|&space| space > 0)
+                    .map_or(hard_end, |space| space + 1)
+            } else {
+                hard_end
+            };
+            wrapped.push(rest[..end].trim_end());
+            // the spaces at a line break would only indent the next line
+            rest = rest[end..].trim_start();
+        }
+        if !rest.is_empty() || wrapped.len() == first {
+            wrapped.push(rest);
+        }
+    }
+
+    wrapped.join("\n")
+}
+
+pub fn truncate_text(text: &str, width: usize) -> String {
+    let first_line = text.lines().next().unwrap_or("");
+    if first_line.width() <= width && !text.contains('\n') {
+        return first_line.to_string();
+    }
+
+    let end = prefix_len(first_line, width.saturating_sub(1));
+    format!("{}…", &first_line[..end])
+}
+
+pub fn center_text(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(text_width(text));
+    let left = padding / 2;
+    format!("{}{}{}", " ".repeat(left), text, " ".repeat(padding - left))
+}
+
+// at least one character, so that wrapping always makes progress
+fn prefix_len(text: &str, width: usize) -> usize {
+    let mut used = 0;
+    for (idx, c) in text.char_indices() {
+        let char_width = unicode_width::UnicodeWidthChar::width(c).unwrap_or(0);
+        if used + char_width > width && idx > 0 {
+            return idx;
+        }
+        used += char_width;
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_text_breaks_at_the_width() {
+        assert_eq!(wrap_text("abcdefgh", 3, false), "abc\ndef\ngh");
+        assert_eq!(wrap_text("short", 10, false), "short");
+    }
+
+    #[test]
+    fn wrap_text_breaks_after_words() {
+        assert_eq!(
+            wrap_text("the quick brown fox", 10, true),
+            "the quick\nbrown fox"
+        );
+        assert_eq!(wrap_text("abcdefghij klm", 4, true), "abcd\nefgh\nij\nklm");
+    }
+
+    #[test]
+    fn wrap_text_keeps_existing_lines() {
+        assert_eq!(wrap_text("ab\n\ncdef", 2, false), "ab\n\ncd\nef");
+    }
+
+    #[test]
+    fn wrap_text_counts_columns_instead_of_bytes() {
+        assert_eq!(wrap_text("äöüß", 2, false), "äö\nüß");
+    }
+}
@@ -1,6 +1,11 @@
+use crate::busy::TimeoutOptions;
+use crate::interrupt::ProgressOptions;
+use crate::once::{self, OnceRedirect};
+use crate::session::Session;
+use crate::trace::TraceSettings;
 use prettytable::Table;
 use prettytable::format::TableFormat;
-use rusqlite::Connection;
+use rusqlite::{Connection, LoadExtensionGuard};
 use std::cell::RefCell;
 use std::fmt;
 use std::fs::File;
@@ -28,9 +33,89 @@ pub enum TableMode {
     Tcl,
 }
 
+impl TableMode {
+    pub fn is_columnar(self) -> bool {
+        matches!(
+            self,
+            TableMode::Box | TableMode::Column | TableMode::Markdown | TableMode::Table
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct UnrecognizedTableMode;
 
+#[derive(Debug, Clone)]
+pub struct Separators {
+    pub column: String,
+    pub row: String,
+}
+
+impl Separators {
+    pub fn for_mode(mode: TableMode) -> Option<Self> {
+        let (column, row) = match mode {
+            TableMode::List => ("|", "\n"),
+            TableMode::Csv => (",", "\n"),
+            TableMode::Tabs => ("\t", "\n"),
+            // the unit and record separators of ASCII
+            TableMode::Ascii => ("\x1F", "\x1E"),
+            _ => return None,
+        };
+
+        Some(Self {
+            column: column.to_string(),
+            row: row.to_string(),
+        })
+    }
+}
+
+impl Default for Separators {
+    fn default() -> Self {
+        Self::for_mode(TableMode::List).expect("list mode has separators")
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ColumnarOptions {
+    // 0 sizes a column by its content, negative widths align it to the right
+    pub widths: Vec<i32>,
+    pub wrap: usize,
+    pub word_wrap: bool,
+    pub quote: bool,
+}
+
+impl ColumnarOptions {
+    // like in the `sqlite3` shell, options that are left out get their default
+    pub fn parse(&self, args: &[&str]) -> Result<Self, String> {
+        let mut opts = Self {
+            widths: self.widths.clone(),
+            ..Self::default()
+        };
+
+        let mut args = args.iter().filter(|arg| !arg.is_empty());
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--wrap" => {
+                    let value = args.next().ok_or("--wrap needs an argument")?;
+                    opts.wrap = value
+                        .parse()
+                        .map_err(|_| format!("invalid value for --wrap: {}", value))?;
+                }
+                "--wordwrap" => {
+                    let value = args.next().ok_or("--wordwrap needs an argument")?;
+                    opts.word_wrap = crate::util::on_or_off_to_bool(value);
+                }
+                "-ww" => opts.word_wrap = true,
+                "--quote" => opts.quote = true,
+                "--noquote" => opts.quote = false,
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
 impl TryFrom<&str> for TableMode {
     type Error = UnrecognizedTableMode;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
@@ -75,6 +160,105 @@ impl From<TableMode> for &str {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum EqpMode {
+    #[default]
+    Off,
+    On,
+    Full,
+    Trigger,
+}
+
+impl TryFrom<&str> for EqpMode {
+    type Error = UnrecognizedMode;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "off" => Ok(EqpMode::Off),
+            "on" => Ok(EqpMode::On),
+            "full" => Ok(EqpMode::Full),
+            "trigger" => Ok(EqpMode::Trigger),
+            _ => Err(UnrecognizedMode),
+        }
+    }
+}
+
+impl From<EqpMode> for &str {
+    fn from(value: EqpMode) -> Self {
+        match value {
+            EqpMode::Off => "off",
+            EqpMode::On => "on",
+            EqpMode::Full => "full",
+            EqpMode::Trigger => "trigger",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum StatsMode {
+    #[default]
+    Off,
+    On,
+    Stmt,
+    VmStep,
+}
+
+impl TryFrom<&str> for StatsMode {
+    type Error = UnrecognizedMode;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "off" => Ok(StatsMode::Off),
+            "on" => Ok(StatsMode::On),
+            "stmt" => Ok(StatsMode::Stmt),
+            "vmstep" => Ok(StatsMode::VmStep),
+            _ => Err(UnrecognizedMode),
+        }
+    }
+}
+
+impl From<StatsMode> for &str {
+    fn from(value: StatsMode) -> Self {
+        match value {
+            StatsMode::Off => "off",
+            StatsMode::On => "on",
+            StatsMode::Stmt => "stmt",
+            StatsMode::VmStep => "vmstep",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ExplainMode {
+    Off,
+    On,
+    #[default]
+    Auto,
+}
+
+impl TryFrom<&str> for ExplainMode {
+    type Error = UnrecognizedMode;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "off" => Ok(ExplainMode::Off),
+            "on" => Ok(ExplainMode::On),
+            "auto" => Ok(ExplainMode::Auto),
+            _ => Err(UnrecognizedMode),
+        }
+    }
+}
+
+impl From<ExplainMode> for &str {
+    fn from(value: ExplainMode) -> Self {
+        match value {
+            ExplainMode::Off => "off",
+            ExplainMode::On => "on",
+            ExplainMode::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnrecognizedMode;
+
 #[derive(Debug)]
 pub struct InvalidRepresentation;
 
@@ -97,14 +281,14 @@ impl TryFrom<TableMode> for TableFormat {
 
 pub enum Output {
     BufferedStdout(BufWriter<Stdout>),
-    BufferedFile(BufWriter<File>),
+    BufferedFile(BufWriter<File>, PathBuf),
 }
 
 impl fmt::Display for Output {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Output::BufferedStdout(_) => f.pad("stdout"),
-            Output::BufferedFile(_) => f.pad("file"),
+            Output::BufferedFile(_, path) => f.pad(&path.display().to_string()),
         }
     }
 }
@@ -115,7 +299,7 @@ impl Output {
             Output::BufferedStdout(buf_stdout) => {
                 let _ = tbl.print(buf_stdout).expect("unable to print all bytes");
             }
-            Output::BufferedFile(buf_file) => {
+            Output::BufferedFile(buf_file, _) => {
                 let _ = tbl.print(buf_file).expect("unable to print all bytes");
             }
         }
@@ -126,7 +310,7 @@ impl Output {
             Output::BufferedStdout(buf_stdout) => buf_stdout
                 .flush()
                 .expect("unable to flush writing to stdout"),
-            Output::BufferedFile(buf_file) => {
+            Output::BufferedFile(buf_file, _) => {
                 buf_file.flush().expect("unable to flush writing to file")
             }
         }
@@ -141,7 +325,23 @@ pub struct Context {
     pub(crate) cwd: PathBuf,
     pub(crate) with_header: bool,
     pub(crate) with_echo: bool,
+    pub(crate) with_timer: bool,
+    pub(crate) eqp: EqpMode,
+    pub(crate) explain: ExplainMode,
+    pub(crate) expert: bool,
     pub(crate) null_value_repr: Option<String>,
+    pub(crate) sessions: Vec<Session>,
+    pub(crate) bail: bool,
+    pub(crate) error_count: usize,
+    pub(crate) once: Option<OnceRedirect>,
+    pub(crate) launcher: String,
+    pub(crate) columnar: ColumnarOptions,
+    pub(crate) separators: Separators,
+    pub(crate) stats: StatsMode,
+    // kept across `.open`, every new connection waits for locks the same way
+    pub(crate) timeout: TimeoutOptions,
+    pub(crate) progress: ProgressOptions,
+    pub(crate) trace: Option<TraceSettings>,
 }
 
 impl Default for Context {
@@ -157,35 +357,85 @@ impl Default for Context {
             cwd: std::env::current_dir().expect("cwd may not exists or insuffiecient permission"),
             with_header: false,
             with_echo: false,
+            with_timer: false,
+            eqp: EqpMode::Off,
+            explain: ExplainMode::Auto,
+            expert: false,
             null_value_repr: None,
+            sessions: Vec::new(),
+            bail: false,
+            error_count: 0,
+            once: None,
+            launcher: once::default_launcher(),
+            columnar: ColumnarOptions::default(),
+            separators: Separators::default(),
+            stats: StatsMode::Off,
+            timeout: TimeoutOptions::default(),
+            progress: ProgressOptions::default(),
+            trace: None,
         }
     }
 }
 
+impl Drop for Context {
+    fn drop(&mut self) {
+        // sessions have to be deleted while their connection is still open
+        self.sessions.clear();
+    }
+}
+
 impl Context {
     pub fn set_conn(&mut self, path: String) {
-        self.cwd.push(&path);
-
-        *self.conn.borrow_mut() =
-            Connection::open(&self.cwd).expect("unable to establish a database connection");
-        self.cwd.pop();
+        self.sessions.clear();
+        *self.conn.borrow_mut() = Connection::open(self.db_path(&path))
+            .expect("unable to establish a database connection");
     }
-    pub fn set_output(&mut self, path: String) {
-        self.cwd.push(&path);
+    // in-memory and temporary databases and `file:` URIs are handed to SQLite as they are
+    pub fn db_path(&self, name: &str) -> PathBuf {
+        if name.is_empty() || name == ":memory:" || name.starts_with("file:") {
+            PathBuf::from(name)
+        } else {
+            self.cwd.join(name)
+        }
+    }
+    // extension loading is only enabled while this runs
+    pub fn load_extension(&self, file: &str, entry: Option<&str>) -> Result<(), String> {
+        // bare names are looked up by the dynamic linker, paths are relative to the shell
+        let path = if file.contains(std::path::MAIN_SEPARATOR) || file.contains('/') {
+            let path = self.cwd.join(file);
+            let found = ["", ".so", ".dylib", ".dll"]
+                .iter()
+                .any(|suffix| PathBuf::from(format!("{}{}", path.display(), suffix)).exists());
+            if !found {
+                return Err(format!("extension \"{}\" not found", file));
+            }
+            path
+        } else {
+            PathBuf::from(file)
+        };
 
-        let dump_file = File::open(&self.cwd).expect("unable to open file");
-        let bufwriter = BufWriter::new(dump_file);
-        self.output = Output::BufferedFile(bufwriter)
+        let conn = self.conn.borrow();
+        // SAFETY: loading an extension runs its code, the user asked for this library
+        let result = unsafe {
+            LoadExtensionGuard::new(&conn).and_then(|_guard| conn.load_extension(&path, entry))
+        };
+        result.map_err(|e| format!("unable to load extension \"{}\": {}", file, e))
     }
 
     pub fn set_mode(&mut self, mode: String) {
         let mode = &mode as &str;
         let result = TableMode::try_from(mode);
         match result {
-            Ok(new_mode) => self.mode = new_mode,
+            Ok(new_mode) => self.switch_mode(new_mode),
             Err(_) => eprintln!("unrecognized table"),
         }
     }
+    pub fn switch_mode(&mut self, mode: TableMode) {
+        self.mode = mode;
+        if let Some(separators) = Separators::for_mode(mode) {
+            self.separators = separators;
+        }
+    }
 
     pub fn set_command(&mut self, command: String) {
         self.command = Some(command);
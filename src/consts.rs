@@ -1,5 +1,6 @@
 use lazy_static::lazy_static;
 use prettytable::format::{FormatBuilder, LinePosition, LineSeparator, TableFormat};
+use rusqlite::limits::Limit;
 
 pub const SQLITE_KEYWORDS: [&str; 147] = [
     "ABORT",
@@ -153,8 +154,26 @@ pub const SQLITE_KEYWORDS: [&str; 147] = [
 
 pub const SQLITE_TYPES: [&str; 6] = ["NULL", "INTEGER", "INT", "REAL", "TEXT", "BLOB"];
 
+pub const SQLITE_LIMITS: [(&str, Limit); 12] = [
+    ("length", Limit::SQLITE_LIMIT_LENGTH),
+    ("sql_length", Limit::SQLITE_LIMIT_SQL_LENGTH),
+    ("column", Limit::SQLITE_LIMIT_COLUMN),
+    ("expr_depth", Limit::SQLITE_LIMIT_EXPR_DEPTH),
+    ("compound_select", Limit::SQLITE_LIMIT_COMPOUND_SELECT),
+    ("vdbe_op", Limit::SQLITE_LIMIT_VDBE_OP),
+    ("function_arg", Limit::SQLITE_LIMIT_FUNCTION_ARG),
+    ("attached", Limit::SQLITE_LIMIT_ATTACHED),
+    (
+        "like_pattern_length",
+        Limit::SQLITE_LIMIT_LIKE_PATTERN_LENGTH,
+    ),
+    ("variable_number", Limit::SQLITE_LIMIT_VARIABLE_NUMBER),
+    ("trigger_depth", Limit::SQLITE_LIMIT_TRIGGER_DEPTH),
+    ("worker_threads", Limit::SQLITE_LIMIT_WORKER_THREADS),
+];
+
 pub const HELP_COMMANDS: [[&str; 3]; 68] = [
-    [".archive", "...", "Manage SQL archives"],
+    [".archive", "?OPTIONS? ?FILE ...?", "Manage SQL archives"],
     [".auth", "ON|OFF", "Show authorizer callbacks"],
     [
         ".backup",
@@ -208,7 +227,11 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
         "Show status information about the database",
     ],
     [".dbtotxt", "", "Hex dump of the database file"],
-    [".dump", "?OBJECTS?", "Render database content as SQL"],
+    [
+        ".dump",
+        "?OPTIONS? ?OBJECTS?",
+        "Render database content as SQL",
+    ],
     [".echo", "on|off", "Turn command echo on or off"],
     [
         ".eqp",
@@ -239,7 +262,11 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
     ],
     [".headers", "on|off", "Turn display of headers on or off"],
     [".help", "?-all? ?PATTERN?", "Show help text for PATTERN"],
-    [".import", "FILE TABLE", "Import data from FILE into TABLE"],
+    [
+        ".import",
+        "?OPTIONS? FILE TABLE",
+        "Import data from FILE into TABLE",
+    ],
     [
         ".imposter",
         "INDEX TABLE",
@@ -289,7 +316,7 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
     [".print", "STRING...", "Print literal STRING"],
     [
         ".progress",
-        "N",
+        "N ?OPTIONS?",
         "Invoke progress handler after every N opcodes",
     ],
     [".prompt", "MAIN CONTINUE", "Replace the standard prompts"],
@@ -301,7 +328,7 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
     [".read", "FILE", "Read input from FILE or command output"],
     [
         ".recover",
-        "",
+        "?OPTIONS?",
         "Recover as much data as possible from corrupt db.",
     ],
     [
@@ -330,10 +357,18 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
         "Change the column and row separators",
     ],
     [".session", "?NAME? CMD ...", "Create or control sessions"],
-    [".sha3sum", "...", "Compute a SHA3 hash of database content"],
+    [
+        ".sha3sum",
+        "?OPTIONS? ?LIKE-PATTERN?",
+        "Compute a SHA3 hash of database content",
+    ],
     [".shell", "CMD ARGS...", "Run CMD ARGS... in a system shell"],
     [".show", "", "Show the current values for various settings"],
-    [".stats", "?ARG?", "Show stats or turn stats on or off"],
+    [
+        ".stats",
+        "?on|off|stmt|vmstep?",
+        "Show stats or turn stats on or off",
+    ],
     [
         ".system",
         "CMD ARGS...",
@@ -346,16 +381,20 @@ pub const HELP_COMMANDS: [[&str; 3]; 68] = [
     ],
     [
         ".timeout",
-        "MS",
+        "MS ?--verbose?",
         "Try opening locked tables for MS milliseconds",
     ],
     [".timer", "on|off", "Turn SQL timer on or off"],
     [
         ".trace",
-        "?OPTIONS?",
+        "?FILE? ?OPTIONS?",
         "Output each SQL statement as it is run",
     ],
-    [".unmodule", "NAME ...", "Unregister virtual table modules"],
+    [
+        ".unmodule",
+        "?--allexcept? NAME ...",
+        "Unregister virtual table modules",
+    ],
     [".version", "", "Show source, library and compiler versions"],
     [".vfsinfo", "?AUX?", "Information about the top-level VFS"],
     [".vfslist", "", "List all available VFSes"],
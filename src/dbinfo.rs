@@ -0,0 +1,91 @@
+use crate::{dbpage, util};
+use rusqlite::Connection;
+use std::io::Write;
+
+const HEADER_MAGIC: &[u8] = b"SQLite format 3\0";
+const HEADER_SIZE: usize = 100;
+
+// the 4 byte big-endian fields of the database header, by offset
+const HEADER_FIELDS: [(&str, usize); 11] = [
+    ("file change counter:", 24),
+    ("database page count:", 28),
+    ("freelist page count:", 36),
+    ("schema cookie:", 40),
+    ("schema format:", 44),
+    ("default cache size:", 48),
+    ("autovacuum top root:", 52),
+    ("incremental vacuum:", 64),
+    ("text encoding:", 56),
+    ("user version:", 60),
+    ("application id:", 68),
+];
+
+pub fn print_db_info(
+    conn: &Connection,
+    db_name: &str,
+    writer: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let page = dbpage::read_pages(conn, db_name, true)?;
+    let header: [u8; HEADER_SIZE] = page
+        .get(..HEADER_SIZE)
+        .and_then(|header| header.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("unable to read the header of \"{}\"", db_name))?;
+    if !header.starts_with(HEADER_MAGIC) {
+        anyhow::bail!("\"{}\" is not a database", db_name);
+    }
+
+    let read_u32 = |at: usize| {
+        u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+    };
+    // a page size of 65536 doesn't fit into two bytes and is stored as 1
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => u32::from(size),
+    };
+
+    let _ = writeln!(writer, "{:<20} {}", "database page size:", page_size);
+    let _ = writeln!(writer, "{:<20} {}", "write format:", header[18]);
+    let _ = writeln!(writer, "{:<20} {}", "read format:", header[19]);
+    let _ = writeln!(writer, "{:<20} {}", "reserved bytes:", header[20]);
+    for (label, offset) in HEADER_FIELDS {
+        let value = read_u32(offset);
+        if offset == 56 {
+            let _ = writeln!(writer, "{:<20} {} ({})", label, value, encoding_name(value));
+        } else {
+            let _ = writeln!(writer, "{:<20} {}", label, value);
+        }
+    }
+    let _ = writeln!(writer, "{:<20} {}", "software version:", read_u32(96));
+
+    let schema = format!("{}.sqlite_schema", util::quote_identifier(db_name));
+    for (label, kind) in [
+        ("number of tables:", "table"),
+        ("number of indexes:", "index"),
+        ("number of triggers:", "trigger"),
+        ("number of views:", "view"),
+    ] {
+        let count: i64 = conn.query_row(
+            &format!("SELECT count(*) FROM {} WHERE type = ?1", schema),
+            [kind],
+            |row| row.get(0),
+        )?;
+        let _ = writeln!(writer, "{:<20} {}", label, count);
+    }
+    let schema_size: i64 = conn.query_row(
+        &format!("SELECT coalesce(sum(length(sql)), 0) FROM {}", schema),
+        [],
+        |row| row.get(0),
+    )?;
+    let _ = writeln!(writer, "{:<20} {}", "schema size:", schema_size);
+
+    Ok(())
+}
+
+fn encoding_name(encoding: u32) -> &'static str {
+    match encoding {
+        1 => "utf8",
+        2 => "utf16le",
+        3 => "utf16be",
+        _ => "unknown",
+    }
+}
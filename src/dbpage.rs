@@ -0,0 +1,133 @@
+use rusqlite::{Connection, OptionalExtension};
+use std::fs::File;
+use std::io::Read;
+
+const WAL_HEADER_SIZE: usize = 32;
+const WAL_FRAME_HEADER_SIZE: usize = 24;
+const WAL_MAGIC: u32 = 0x377f0682;
+
+// the raw pages of the database `db_name` as SQLite sees them, with the frames that are
+// still in the write-ahead log. `first_page_only` stops after page 1
+pub fn read_pages(
+    conn: &Connection,
+    db_name: &str,
+    first_page_only: bool,
+) -> anyhow::Result<Vec<u8>> {
+    // sqlite_dbpage reads through the pager, it only exists with SQLITE_ENABLE_DBPAGE_VTAB
+    let sql = if first_page_only {
+        "SELECT data FROM sqlite_dbpage(?1) WHERE pgno = 1"
+    } else {
+        "SELECT data FROM sqlite_dbpage(?1) ORDER BY pgno"
+    };
+    if let Ok(mut stmt) = conn.prepare(sql) {
+        let pages = stmt
+            .query_map([db_name], |row| row.get::<_, Vec<u8>>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        return Ok(pages.concat());
+    }
+
+    let file: Option<String> = conn
+        .query_row(
+            "SELECT file FROM pragma_database_list WHERE name = ?1",
+            [db_name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let file = file.ok_or_else(|| anyhow::anyhow!("no such database: {}", db_name))?;
+    if file.is_empty() {
+        anyhow::bail!("the database \"{}\" is not a file", db_name);
+    }
+
+    let mut data = Vec::new();
+    let mut reader = File::open(&file)?;
+    if first_page_only {
+        // no page is larger than 64k
+        reader.by_ref().take(65536).read_to_end(&mut data)?;
+    } else {
+        reader.read_to_end(&mut data)?;
+    }
+
+    // a missing or unreadable log means that everything is in the database file
+    if let Ok(wal) = std::fs::read(format!("{}-wal", file)) {
+        apply_wal(&mut data, &wal, first_page_only);
+    }
+    Ok(data)
+}
+
+// copies the committed frames of the write-ahead log `wal` over the pages in `data`. Frames
+// count as long as their salt and their checksum continue the log, like SQLite's recovery
+fn apply_wal(data: &mut Vec<u8>, wal: &[u8], first_page_only: bool) {
+    if wal.len() < WAL_HEADER_SIZE {
+        return;
+    }
+    let read_u32 = |bytes: &[u8], at: usize| {
+        u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    };
+
+    let magic = read_u32(wal, 0);
+    if magic & !1 != WAL_MAGIC {
+        return;
+    }
+    // the lowest bit of the magic number tells the byte order of the checksums
+    let big_endian = magic & 1 == 1;
+    let page_size = read_u32(wal, 8) as usize;
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+        return;
+    }
+    let salt = &wal[16..24];
+
+    let mut checksum = wal_checksum(&wal[..24], (0, 0), big_endian);
+    if checksum != (read_u32(wal, 24), read_u32(wal, 28)) {
+        return;
+    }
+
+    let mut pending: Vec<(u32, &[u8])> = Vec::new();
+    let frame_size = WAL_FRAME_HEADER_SIZE + page_size;
+    for frame in wal[WAL_HEADER_SIZE..].chunks_exact(frame_size) {
+        let (header, page) = frame.split_at(WAL_FRAME_HEADER_SIZE);
+        if &header[8..16] != salt {
+            break;
+        }
+        checksum = wal_checksum(&header[..8], checksum, big_endian);
+        checksum = wal_checksum(page, checksum, big_endian);
+        if checksum != (read_u32(header, 16), read_u32(header, 20)) {
+            break;
+        }
+
+        pending.push((read_u32(header, 0), page));
+        // frames only count once a transaction commits, the commit frame has the page count
+        let page_count = read_u32(header, 4) as usize;
+        if page_count == 0 {
+            continue;
+        }
+        for (pgno, page) in pending.drain(..) {
+            if pgno == 0 || (first_page_only && pgno != 1) {
+                continue;
+            }
+            let start = (pgno as usize - 1) * page_size;
+            if data.len() < start + page_size {
+                data.resize(start + page_size, 0);
+            }
+            data[start..start + page_size].copy_from_slice(page);
+        }
+        if !first_page_only {
+            data.resize(page_count * page_size, 0);
+        }
+    }
+}
+
+fn wal_checksum(bytes: &[u8], (mut s0, mut s1): (u32, u32), big_endian: bool) -> (u32, u32) {
+    let word = |chunk: &[u8]| {
+        let chunk = [chunk[0], chunk[1], chunk[2], chunk[3]];
+        if big_endian {
+            u32::from_be_bytes(chunk)
+        } else {
+            u32::from_le_bytes(chunk)
+        }
+    };
+    for pair in bytes.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
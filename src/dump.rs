@@ -0,0 +1,256 @@
+use crate::util;
+use rusqlite::{Connection, OptionalExtension};
+use std::io::Write;
+
+#[derive(Debug, Default)]
+pub struct DumpOptions {
+    pub data_only: bool,
+    pub schema_only: bool,
+    pub preserve_rowids: bool,
+    pub newlines: bool,
+    pub patterns: Vec<String>,
+}
+
+impl DumpOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut opts = Self::default();
+
+        for &arg in args.iter().filter(|arg| !arg.is_empty()) {
+            match arg {
+                "--data-only" => opts.data_only = true,
+                "--schema-only" => opts.schema_only = true,
+                "--preserve-rowids" => opts.preserve_rowids = true,
+                "--newlines" => opts.newlines = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => opts.patterns.push(arg.to_string()),
+            }
+        }
+
+        if opts.data_only && opts.schema_only {
+            return Err("--data-only and --schema-only are mutually exclusive".to_string());
+        }
+
+        Ok(opts)
+    }
+
+    fn filter(&self, column: &str) -> String {
+        if self.patterns.is_empty() {
+            return "1".to_string();
+        }
+
+        let conditions = self
+            .patterns
+            .iter()
+            .map(|pattern| {
+                format!(
+                    "{} LIKE {} ESCAPE '\\'",
+                    column,
+                    util::quote_string_literal(pattern)
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("({})", conditions.join(" OR "))
+    }
+}
+
+pub fn dump_database(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    opts: &DumpOptions,
+) -> rusqlite::Result<()> {
+    if !opts.data_only {
+        let _ = writeln!(writer, "PRAGMA foreign_keys=OFF;");
+    }
+    let _ = writeln!(writer, "BEGIN TRANSACTION;");
+
+    match dump_objects(conn, writer, opts) {
+        Ok(writable_schema) => {
+            if writable_schema {
+                let _ = writeln!(writer, "PRAGMA writable_schema=OFF;");
+            }
+            let _ = writeln!(writer, "COMMIT;");
+            writer.flush().expect("unable to flush");
+            Ok(())
+        }
+        Err(e) => {
+            // whatever was written so far shouldn't be committed when it gets restored
+            let _ = writeln!(writer, "ROLLBACK; -- due to errors");
+            writer.flush().expect("unable to flush");
+            Err(e)
+        }
+    }
+}
+
+// tables come first, so that the objects that depend on them can be created
+fn dump_objects(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    opts: &DumpOptions,
+) -> rusqlite::Result<bool> {
+    let mut writable_schema = false;
+
+    // `sqlite_sequence` goes last, its content is only valid after the other tables are filled
+    let tables_sql = format!(
+        "SELECT name, sql FROM sqlite_schema WHERE {} AND type = 'table' AND sql NOT NULL \
+         ORDER BY tbl_name = 'sqlite_sequence', rowid",
+        opts.filter("name")
+    );
+    let mut stmt = conn.prepare(&tables_sql)?;
+    let tables = stmt
+        .query_map((), |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (name, sql) in tables {
+        if name == "sqlite_sequence" {
+            if !opts.data_only {
+                let _ = writeln!(writer, "DELETE FROM sqlite_sequence;");
+            }
+        } else if name == "sqlite_stat1" {
+            if !opts.data_only {
+                let _ = writeln!(writer, "ANALYZE sqlite_schema;");
+            }
+        } else if name.starts_with("sqlite_") {
+            continue;
+        } else if sql.starts_with("CREATE VIRTUAL TABLE") {
+            // virtual tables can't be created with their original statement when the module
+            // isn't loaded yet, so the schema entry is written directly
+            if !opts.data_only {
+                if !writable_schema {
+                    let _ = writeln!(writer, "PRAGMA writable_schema=ON;");
+                    writable_schema = true;
+                }
+                let _ = writeln!(
+                    writer,
+                    "INSERT INTO sqlite_schema(type,name,tbl_name,rootpage,sql)VALUES('table',{},{},0,{});",
+                    util::quote_string_literal(&name),
+                    util::quote_string_literal(&name),
+                    util::quote_string_literal(&sql)
+                );
+            }
+            continue;
+        } else if !opts.data_only {
+            let _ = writeln!(writer, "{};", sql);
+        }
+
+        if !opts.schema_only {
+            dump_table_content(conn, writer, &name, opts)?;
+        }
+    }
+
+    if !opts.data_only {
+        let others_sql = format!(
+            "SELECT sql FROM sqlite_schema WHERE {} AND sql NOT NULL \
+             AND type IN ('index', 'trigger', 'view') ORDER BY type COLLATE NOCASE DESC, rowid",
+            opts.filter("tbl_name")
+        );
+        let mut stmt = conn.prepare(&others_sql)?;
+        let statements = stmt
+            .query_map((), |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        for sql in statements {
+            let _ = writeln!(writer, "{};", sql);
+        }
+    }
+
+    Ok(writable_schema)
+}
+
+fn dump_table_content(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    table: &str,
+    opts: &DumpOptions,
+) -> rusqlite::Result<()> {
+    // generated columns are hidden, they are computed again when the rows are restored
+    let columns_sql = "SELECT name, hidden FROM pragma_table_xinfo(?1)";
+    let mut stmt = conn.prepare(columns_sql)?;
+    let all_columns = stmt
+        .query_map([table], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let has_hidden = all_columns.iter().any(|(_, hidden)| *hidden != 0);
+    let mut columns: Vec<String> = all_columns
+        .into_iter()
+        .filter(|(_, hidden)| *hidden == 0)
+        .map(|(name, _)| name)
+        .collect();
+
+    let rowid = if opts.preserve_rowids {
+        rowid_column(conn, table, &columns)?
+    } else {
+        None
+    };
+    if let Some(rowid) = rowid {
+        columns.insert(0, rowid.to_string());
+    }
+
+    let quoted_table = util::quote_identifier_if_needed(table);
+    let quoted_columns = columns
+        .iter()
+        .map(|col| util::quote_identifier_if_needed(col))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // the column list is only needed when the insert doesn't cover every column in order
+    let target = if rowid.is_some() || has_hidden {
+        format!("{}({})", quoted_table, quoted_columns)
+    } else {
+        quoted_table.clone()
+    };
+
+    let select_sql = format!("SELECT {} FROM {}", quoted_columns, quoted_table);
+    let mut stmt = conn.prepare(&select_sql)?;
+    let col_count = stmt.column_count();
+    let mut rows = stmt.query(())?;
+
+    while let Some(row) = rows.next()? {
+        let mut values = Vec::with_capacity(col_count);
+        for col_idx in 0..col_count {
+            values.push(util::sql_literal(row.get_ref(col_idx)?, opts.newlines));
+        }
+        let _ = writeln!(
+            writer,
+            "INSERT INTO {} VALUES({});",
+            target,
+            values.join(",")
+        );
+    }
+
+    Ok(())
+}
+
+// `None` for `WITHOUT ROWID` tables and when an `INTEGER PRIMARY KEY` already aliases the rowid
+fn rowid_column(
+    conn: &Connection,
+    table: &str,
+    columns: &[String],
+) -> rusqlite::Result<Option<&'static str>> {
+    let without_rowid = conn
+        .query_row(
+            "SELECT wr FROM pragma_table_list WHERE schema = 'main' AND name = ?1",
+            [table],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()?
+        .unwrap_or(false);
+    if without_rowid {
+        return Ok(None);
+    }
+
+    let ipk_sql = "SELECT count(*) = 1 AND max(pk = 1 AND upper(type) = 'INTEGER') \
+                   FROM pragma_table_info(?1) WHERE pk > 0";
+    let has_ipk = conn.query_row(ipk_sql, [table], |row| row.get::<_, Option<bool>>(0))?;
+    if has_ipk.unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let rowid = ["rowid", "_rowid_", "oid"]
+        .into_iter()
+        .find(|alias| !columns.iter().any(|col| col.eq_ignore_ascii_case(alias)));
+
+    Ok(rowid)
+}
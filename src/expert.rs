@@ -0,0 +1,507 @@
+use crate::{consts, explain, util};
+use rusqlite::{Connection, params_from_iter};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word { text: String, quoted: bool },
+    Literal,
+    Symbol(String),
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word { text, quoted: false } if text.eq_ignore_ascii_case(keyword))
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self, Token::Symbol(s) if s == symbol)
+    }
+
+    fn as_name(&self) -> Option<&str> {
+        match self {
+            Token::Word { text, quoted: true } => Some(text),
+            Token::Word {
+                text,
+                quoted: false,
+            } if !consts::is_sqlite_keyword(&text.to_uppercase()) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let chars: Vec<char> = sql.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    // returns the index right after the closing `close`, doubled closing chars are escapes
+    let skip_quoted = |start: usize, close: char| {
+        let mut idx = start + 1;
+        while idx < chars.len() {
+            if chars[idx] == close {
+                if chars.get(idx + 1) == Some(&close) && close != ']' {
+                    idx += 2;
+                    continue;
+                }
+                return idx + 1;
+            }
+            idx += 1;
+        }
+        chars.len()
+    };
+
+    while idx < chars.len() {
+        let ch = chars[idx];
+        match ch {
+            _ if ch.is_whitespace() => idx += 1,
+            '-' if chars.get(idx + 1) == Some(&'-') => {
+                while idx < chars.len() && chars[idx] != '\n' {
+                    idx += 1;
+                }
+            }
+            '/' if chars.get(idx + 1) == Some(&'*') => {
+                idx += 2;
+                while idx < chars.len() && !(chars[idx - 1] == '*' && chars[idx] == '/') {
+                    idx += 1;
+                }
+                idx += 1;
+            }
+            '\'' => {
+                idx = skip_quoted(idx, '\'');
+                tokens.push(Token::Literal);
+            }
+            '"' | '`' | '[' => {
+                let close = if ch == '[' { ']' } else { ch };
+                let end = skip_quoted(idx, close);
+                let inner: String = chars[idx + 1..end.saturating_sub(1).max(idx + 1)]
+                    .iter()
+                    .collect();
+                let doubled = format!("{}{}", close, close);
+                tokens.push(Token::Word {
+                    text: inner.replace(&doubled, &close.to_string()),
+                    quoted: true,
+                });
+                idx = end;
+            }
+            '?' | ':' | '@' | '$' => {
+                idx += 1;
+                while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '_') {
+                    idx += 1;
+                }
+                tokens.push(Token::Literal);
+            }
+            _ if ch.is_ascii_digit()
+                || (ch == '.' && chars.get(idx + 1).is_some_and(char::is_ascii_digit)) =>
+            {
+                while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '.') {
+                    idx += 1;
+                }
+                tokens.push(Token::Literal);
+            }
+            _ if ch.is_alphabetic() || ch == '_' => {
+                let start = idx;
+                while idx < chars.len() && (chars[idx].is_alphanumeric() || chars[idx] == '_') {
+                    idx += 1;
+                }
+                let text: String = chars[start..idx].iter().collect();
+                // blob literals look like an identifier followed by a string
+                if text.eq_ignore_ascii_case("x") && chars.get(idx) == Some(&'\'') {
+                    idx = skip_quoted(idx, '\'');
+                    tokens.push(Token::Literal);
+                } else {
+                    tokens.push(Token::Word {
+                        text,
+                        quoted: false,
+                    });
+                }
+            }
+            _ => {
+                let two: String = chars[idx..(idx + 2).min(chars.len())].iter().collect();
+                if matches!(two.as_str(), "==" | "<=" | ">=" | "!=" | "<>" | "||") {
+                    tokens.push(Token::Symbol(two));
+                    idx += 2;
+                } else {
+                    tokens.push(Token::Symbol(ch.to_string()));
+                    idx += 1;
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+#[derive(Debug)]
+struct ColumnRef {
+    qualifier: Option<String>,
+    column: String,
+}
+
+#[derive(Debug, Default)]
+struct TableConstraints {
+    equality: Vec<String>,
+    range: Vec<String>,
+    order_by: Vec<String>,
+}
+
+impl TableConstraints {
+    fn push_unique(list: &mut Vec<String>, column: String) {
+        if !list.contains(&column) {
+            list.push(column);
+        }
+    }
+
+    fn candidates(&self) -> Vec<Vec<String>> {
+        let mut column_sets: Vec<Vec<String>> = Vec::new();
+        if self.equality.len() > 1 {
+            column_sets.extend(self.equality.iter().map(|col| vec![col.clone()]));
+        }
+        if !self.equality.is_empty() {
+            column_sets.push(self.equality.clone());
+        }
+        for col in self.range.iter().filter(|col| !self.equality.contains(col)) {
+            column_sets.push(self.equality.iter().chain([col]).cloned().collect());
+        }
+        if !self.order_by.is_empty() {
+            let mut columns = self.equality.clone();
+            columns.extend(
+                self.order_by
+                    .iter()
+                    .filter(|col| !self.equality.contains(col))
+                    .cloned(),
+            );
+            column_sets.push(columns);
+        }
+        column_sets
+    }
+}
+
+#[derive(Debug, Default)]
+struct Constraints {
+    equality: Vec<ColumnRef>,
+    range: Vec<ColumnRef>,
+    order_by: Vec<(ColumnRef, bool)>,
+}
+
+fn column_ending_at(tokens: &[Token], end: usize) -> Option<ColumnRef> {
+    let column = tokens.get(end)?.as_name()?.to_string();
+    // a name directly followed by `(` is a function call
+    if tokens.get(end + 1).is_some_and(|t| t.is_symbol("(")) {
+        return None;
+    }
+    let qualifier = (end >= 2 && tokens[end - 1].is_symbol("."))
+        .then(|| tokens[end - 2].as_name().map(str::to_string))
+        .flatten();
+    Some(ColumnRef { qualifier, column })
+}
+
+fn column_starting_at(tokens: &[Token], start: usize) -> Option<ColumnRef> {
+    if tokens.get(start + 1).is_some_and(|t| t.is_symbol(".")) {
+        let qualifier = tokens[start].as_name()?.to_string();
+        let column = tokens.get(start + 2)?.as_name()?.to_string();
+        return Some(ColumnRef {
+            qualifier: Some(qualifier),
+            column,
+        });
+    }
+    column_ending_at(tokens, start)
+}
+
+fn find_constraints(tokens: &[Token]) -> Constraints {
+    let mut constraints = Constraints::default();
+
+    for (idx, token) in tokens.iter().enumerate() {
+        let is_equality = token.is_symbol("=")
+            || token.is_symbol("==")
+            || token.is_keyword("IN")
+            || (token.is_keyword("IS")
+                && !tokens.get(idx + 1).is_some_and(|t| t.is_keyword("NOT")));
+        let is_range = ["<", "<=", ">", ">="].iter().any(|op| token.is_symbol(op))
+            || token.is_keyword("BETWEEN");
+        if !is_equality && !is_range {
+            continue;
+        }
+
+        let list = if is_equality {
+            &mut constraints.equality
+        } else {
+            &mut constraints.range
+        };
+        if let Some(column) = idx
+            .checked_sub(1)
+            .and_then(|end| column_ending_at(tokens, end))
+        {
+            list.push(column);
+        }
+        // comparison operators are symmetric, `IN`, `IS` and `BETWEEN` only constrain the
+        // column on their left side
+        if matches!(token, Token::Symbol(_))
+            && let Some(column) = column_starting_at(tokens, idx + 1)
+        {
+            list.push(column);
+        }
+    }
+
+    if let Some(order_idx) = tokens
+        .windows(2)
+        .rposition(|pair| pair[0].is_keyword("ORDER") && pair[1].is_keyword("BY"))
+    {
+        let mut idx = order_idx + 2;
+        while idx < tokens.len() {
+            let end = (idx..tokens.len())
+                .find(|&i| {
+                    tokens[i].is_symbol(",")
+                        || tokens[i].is_symbol(")")
+                        || tokens[i].is_symbol(";")
+                        || tokens[i].is_keyword("LIMIT")
+                })
+                .unwrap_or(tokens.len());
+
+            let term = &tokens[idx..end];
+            let descending = term.iter().any(|t| t.is_keyword("DESC"));
+            if let Some(column) = column_starting_at(tokens, idx)
+                && term.len() <= 4
+            {
+                constraints.order_by.push((column, descending));
+            }
+
+            if end >= tokens.len() || !tokens[end].is_symbol(",") {
+                break;
+            }
+            idx = end + 1;
+        }
+    }
+
+    constraints
+}
+
+fn find_tables(tokens: &[Token]) -> Vec<(String, Option<String>)> {
+    let mut tables = Vec::new();
+
+    for (idx, token) in tokens.iter().enumerate() {
+        if !(token.is_keyword("FROM") || token.is_keyword("JOIN") || token.is_keyword("UPDATE")) {
+            continue;
+        }
+
+        let mut idx = idx + 1;
+        while let Some(name) = tokens.get(idx).and_then(Token::as_name) {
+            // skip the schema name of `schema.table`
+            let (name, next) = match (tokens.get(idx + 1), tokens.get(idx + 2)) {
+                (Some(dot), Some(table)) if dot.is_symbol(".") && table.as_name().is_some() => {
+                    (table.as_name().unwrap_or(name), idx + 3)
+                }
+                _ => (name, idx + 1),
+            };
+
+            let mut alias_idx = next;
+            if tokens.get(alias_idx).is_some_and(|t| t.is_keyword("AS")) {
+                alias_idx += 1;
+            }
+            let alias = tokens.get(alias_idx).and_then(Token::as_name);
+            tables.push((name.to_string(), alias.map(str::to_string)));
+
+            let after = if alias.is_some() { alias_idx + 1 } else { next };
+            // `FROM a, b` lists more than one table
+            if tokens.get(after).is_some_and(|t| t.is_symbol(",")) {
+                idx = after + 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    tables
+}
+
+// hypothetical indexes are created in a copy of the schema, the real database isn't touched
+fn scratch_copy(conn: &Connection) -> rusqlite::Result<Connection> {
+    let scratch = Connection::open_in_memory()?;
+
+    let sql = "SELECT sql FROM sqlite_schema WHERE sql NOT NULL AND type IN ('table', 'index', 'view') \
+               AND name NOT LIKE 'sqlite_%' ORDER BY rowid";
+    let mut stmt = conn.prepare(sql)?;
+    let statements = stmt
+        .query_map((), |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for sql in statements {
+        // virtual tables whose module isn't available here can't be copied, skip them
+        let _ = scratch.execute(&sql, ());
+    }
+
+    // the statistics gathered by `ANALYZE` make the plans on the copy more realistic
+    let has_stats: bool = conn.query_row(
+        "SELECT count(*) FROM sqlite_schema WHERE name = 'sqlite_stat1'",
+        (),
+        |row| row.get(0),
+    )?;
+    if has_stats {
+        scratch.execute_batch("ANALYZE sqlite_schema")?;
+        let mut stmt = conn.prepare("SELECT tbl, idx, stat FROM sqlite_stat1")?;
+        let mut rows = stmt.query(())?;
+        while let Some(row) = rows.next()? {
+            scratch.execute(
+                "INSERT INTO sqlite_stat1(tbl, idx, stat) VALUES (?1, ?2, ?3)",
+                (
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, String>(2)?,
+                ),
+            )?;
+        }
+        scratch.execute_batch("ANALYZE sqlite_schema")?;
+    }
+
+    Ok(scratch)
+}
+
+fn table_columns(conn: &Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    stmt.query_map([table], |row| row.get(0))?.collect()
+}
+
+// FNV-1a keeps the names of suggested indexes stable between runs
+fn name_hash(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+pub fn suggest_indexes(
+    conn: &Connection,
+    query: &str,
+    writer: &mut dyn Write,
+) -> rusqlite::Result<()> {
+    let scratch = scratch_copy(conn)?;
+    let eqp_sql = format!("EXPLAIN QUERY PLAN {}", query);
+
+    // preparing the plan first reports syntax errors and unknown tables
+    let mut before_stmt = scratch.prepare(&eqp_sql)?;
+    let param_count = before_stmt.parameter_count();
+    let mut before = Vec::new();
+    explain::print_query_plan(
+        &mut before,
+        &mut before_stmt,
+        params_from_iter(vec![rusqlite::types::Null; param_count]),
+    )?;
+    drop(before_stmt);
+
+    let tokens = tokenize(query);
+    let tables = find_tables(&tokens);
+    let constraints = find_constraints(&tokens);
+
+    // group every constrained column under the table that it belongs to
+    let mut columns_of: HashMap<&str, Vec<String>> = HashMap::new();
+    for (table, _) in &tables {
+        columns_of.insert(table, table_columns(&scratch, table)?);
+    }
+    let resolve = |column: &ColumnRef| -> Vec<(String, String)> {
+        tables
+            .iter()
+            .filter(|(table, alias)| match &column.qualifier {
+                Some(q) => {
+                    q.eq_ignore_ascii_case(table)
+                        || alias.as_ref().is_some_and(|a| a.eq_ignore_ascii_case(q))
+                }
+                None => true,
+            })
+            .filter_map(|(table, _)| {
+                columns_of[table.as_str()]
+                    .iter()
+                    .find(|name| name.eq_ignore_ascii_case(&column.column))
+                    .map(|name| (table.clone(), util::quote_identifier_if_needed(name)))
+            })
+            .collect()
+    };
+
+    let mut per_table: BTreeMap<String, TableConstraints> = BTreeMap::new();
+    for column in &constraints.equality {
+        for (table, name) in resolve(column) {
+            TableConstraints::push_unique(&mut per_table.entry(table).or_default().equality, name);
+        }
+    }
+    for column in &constraints.range {
+        for (table, name) in resolve(column) {
+            TableConstraints::push_unique(&mut per_table.entry(table).or_default().range, name);
+        }
+    }
+    for (column, descending) in &constraints.order_by {
+        for (table, mut name) in resolve(column) {
+            if *descending {
+                name.push_str(" DESC");
+            }
+            TableConstraints::push_unique(&mut per_table.entry(table).or_default().order_by, name);
+        }
+    }
+
+    // every table gets a handful of candidates, the planner decides which one is the best
+    let mut candidates: Vec<(String, String, String)> = Vec::new();
+    for (table, table_constraints) in &per_table {
+        for columns in table_constraints.candidates() {
+            let columns = columns.join(", ");
+            let name = format!(
+                "{}_idx_{:08x}",
+                table,
+                name_hash(&format!("{}({})", table, columns))
+            );
+            if !candidates
+                .iter()
+                .any(|(candidate, _, _)| *candidate == name)
+            {
+                candidates.push((name, table.clone(), columns));
+            }
+        }
+    }
+
+    for (name, table, columns) in &candidates {
+        let sql = format!(
+            "CREATE INDEX {} ON {}({})",
+            util::quote_identifier_if_needed(name),
+            util::quote_identifier_if_needed(table),
+            columns
+        );
+        // a candidate that the copy rejects can't be suggested either
+        let _ = scratch.execute(&sql, ());
+    }
+
+    let mut after_stmt = scratch.prepare(&eqp_sql)?;
+    let mut after = Vec::new();
+    explain::print_query_plan(
+        &mut after,
+        &mut after_stmt,
+        params_from_iter(vec![rusqlite::types::Null; param_count]),
+    )?;
+    let after = String::from_utf8_lossy(&after).to_string();
+
+    let suggested: Vec<_> = candidates
+        .iter()
+        .filter(|(name, _, _)| {
+            after.contains(&format!("INDEX {} ", name))
+                || after.contains(&format!("INDEX {}\n", name))
+        })
+        .collect();
+
+    if suggested.is_empty() {
+        let _ = writeln!(writer, "(no new indexes)");
+    }
+    for (name, table, columns) in &suggested {
+        let _ = writeln!(
+            writer,
+            "CREATE INDEX {} ON {}({});",
+            util::quote_identifier_if_needed(name),
+            util::quote_identifier_if_needed(table),
+            columns
+        );
+    }
+
+    let _ = writeln!(writer, "\n-- plan without the suggested indexes");
+    let _ = writer.write_all(&before);
+    if !suggested.is_empty() {
+        let _ = writeln!(writer, "\n-- plan with the suggested indexes");
+        let _ = write!(writer, "{}", after);
+    }
+    writer.flush().expect("unable to flush");
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use crate::config::TableMode;
+use crate::util;
+use rusqlite::{Params, Statement};
+use std::io::Write;
+
+const LOOP_END_OPCODES: [&str; 6] = ["Next", "Prev", "VPrev", "VNext", "SorterNext", "Return"];
+// a loop that starts with one of these is closed by a `Goto`
+const LOOP_START_OPCODES: [&str; 5] = ["Yield", "SeekLT", "SeekGT", "RowSetRead", "Rewind"];
+
+const BYTECODE_COLUMNS: [(&str, usize); 8] = [
+    ("addr", 4),
+    ("opcode", 13),
+    ("p1", 4),
+    ("p2", 4),
+    ("p3", 4),
+    ("p4", 13),
+    ("p5", 2),
+    ("comment", 13),
+];
+
+pub fn print_query_plan(
+    writer: &mut dyn Write,
+    stmt: &mut Statement,
+    params: impl Params,
+) -> rusqlite::Result<()> {
+    let rows = stmt
+        .query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let _ = writeln!(writer, "QUERY PLAN");
+    write_plan_children(writer, &rows, 0, "");
+    writer.flush().expect("unable to flush");
+
+    Ok(())
+}
+
+fn write_plan_children(
+    writer: &mut dyn Write,
+    rows: &[(i64, i64, String)],
+    parent: i64,
+    prefix: &str,
+) {
+    let children: Vec<_> = rows.iter().filter(|(_, p, _)| *p == parent).collect();
+
+    for (idx, (id, _, detail)) in children.iter().enumerate() {
+        let is_last = idx == children.len() - 1;
+        let (branch, indent) = if is_last {
+            ("`--", "   ")
+        } else {
+            ("|--", "|  ")
+        };
+        let _ = writeln!(writer, "{}{}{}", prefix, branch, detail);
+        write_plan_children(writer, rows, *id, &format!("{}{}", prefix, indent));
+    }
+}
+
+pub fn print_bytecode(
+    writer: &mut dyn Write,
+    stmt: &mut Statement,
+    params: impl Params,
+) -> rusqlite::Result<()> {
+    let col_count = stmt.column_count();
+    let mut rows = util::query_data_rows(stmt, params, col_count, TableMode::List, None)?;
+    let indents = loop_indents(&rows);
+
+    for (row, indent) in rows.iter_mut().zip(indents) {
+        if let Some(opcode) = row.get_mut(1) {
+            *opcode = format!("{}{}", " ".repeat(indent), opcode);
+        }
+    }
+
+    let titles = BYTECODE_COLUMNS.map(|(title, _)| title.to_string());
+    print_columns(writer, &titles, &rows);
+
+    Ok(())
+}
+
+pub fn print_columns(writer: &mut dyn Write, titles: &[String], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = titles
+        .iter()
+        .enumerate()
+        .map(|(idx, title)| {
+            BYTECODE_COLUMNS
+                .get(idx)
+                .map_or(title.len(), |(_, width)| *width)
+        })
+        .collect();
+    let rules: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    for row in [titles, rules.as_slice()]
+        .into_iter()
+        .chain(rows.iter().map(|row| row.as_slice()))
+    {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let _ = writeln!(writer, "{}", line.trim_end());
+    }
+    writer.flush().expect("unable to flush");
+}
+
+fn loop_indents(rows: &[Vec<String>]) -> Vec<usize> {
+    let mut indents = vec![0; rows.len()];
+    let mut starts_loop = vec![false; rows.len()];
+    let int_at = |row: &Vec<String>, idx: usize| {
+        row.get(idx)
+            .and_then(|value| value.parse::<i64>().ok())
+            .unwrap_or(0)
+    };
+
+    for (op_idx, row) in rows.iter().enumerate() {
+        let opcode = row.get(1).map(String::as_str).unwrap_or_default();
+        let addr = int_at(row, 0);
+        let p1 = int_at(row, 2);
+        // addresses restart inside of trigger sub-programs, translate `p2` into a row index
+        let target = int_at(row, 3) + (op_idx as i64 - addr);
+
+        starts_loop[op_idx] = LOOP_START_OPCODES.contains(&opcode);
+
+        let is_loop_end = LOOP_END_OPCODES.contains(&opcode) && target > 0;
+        let is_loop_goto = opcode == "Goto"
+            && (0..op_idx as i64).contains(&target)
+            && (starts_loop[target as usize] || p1 != 0);
+
+        if is_loop_end || is_loop_goto {
+            for indent in &mut indents[(target.max(0) as usize).min(op_idx)..op_idx] {
+                *indent += 2;
+            }
+        }
+    }
+
+    indents
+}
@@ -0,0 +1,357 @@
+use crate::util;
+use anyhow::{Context as _, bail};
+use csv::{ReaderBuilder, StringRecord, Terminator};
+use rusqlite::{Connection, OptionalExtension, params_from_iter};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+const SAMPLE_ROWS: usize = 100;
+
+#[derive(Debug)]
+pub struct ImportOptions {
+    pub file: String,
+    pub table: String,
+    pub schema: Option<String>,
+    pub skip: usize,
+    pub col_sep: u8,
+    pub row_sep: Option<u8>,
+    pub quoting: bool,
+    pub verbose: bool,
+}
+
+impl ImportOptions {
+    // without a format option, `.tsv` and `.tab` files are split on tabs
+    pub fn parse(args: &[&str], default_sep: u8) -> Result<Self, String> {
+        let mut positional = Vec::with_capacity(2);
+        let mut explicit_format = false;
+        let mut opts = Self {
+            file: String::new(),
+            table: String::new(),
+            schema: None,
+            skip: 0,
+            col_sep: default_sep,
+            row_sep: None,
+            quoting: true,
+            verbose: false,
+        };
+
+        let mut args = args.iter().filter(|arg| !arg.is_empty());
+        while let Some(&arg) = args.next() {
+            explicit_format |= matches!(arg, "--csv" | "--tsv" | "--ascii" | "--colsep");
+            match arg {
+                "--csv" => {
+                    opts.col_sep = b',';
+                    opts.row_sep = None;
+                    opts.quoting = true;
+                }
+                "--tsv" => {
+                    opts.col_sep = b'\t';
+                    opts.row_sep = None;
+                    opts.quoting = true;
+                }
+                "--ascii" => {
+                    opts.col_sep = 0x1F;
+                    opts.row_sep = Some(0x1E);
+                    opts.quoting = false;
+                }
+                "--colsep" | "--rowsep" => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("{} needs an argument", arg))?;
+                    let sep = single_byte_separator(value)?;
+                    if arg == "--colsep" {
+                        opts.col_sep = sep;
+                    } else {
+                        opts.row_sep = Some(sep);
+                    }
+                }
+                "--skip" => {
+                    let value = args.next().ok_or("--skip needs an argument")?;
+                    opts.skip = value
+                        .parse()
+                        .map_err(|_| format!("invalid value for --skip: {}", value))?;
+                }
+                "--schema" => {
+                    let value = args.next().ok_or("--schema needs an argument")?;
+                    opts.schema = Some(value.to_string());
+                }
+                "-v" => opts.verbose = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        match positional[..] {
+            [file, table] => {
+                opts.file = file.to_string();
+                opts.table = table.to_string();
+            }
+            _ => return Err(".import needs a FILE and a TABLE argument".to_string()),
+        }
+
+        if !explicit_format && (opts.file.ends_with(".tsv") || opts.file.ends_with(".tab")) {
+            opts.col_sep = b'\t';
+        }
+
+        Ok(opts)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub rejected: usize,
+    pub created: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    fn of(value: &str) -> Option<Self> {
+        if value.trim().is_empty() {
+            return None;
+        }
+
+        // a number only counts if storing it doesn't change it, "007" or "1.50" stay text
+        if value
+            .parse::<i64>()
+            .is_ok_and(|int| int.to_string() == value)
+        {
+            Some(ColumnType::Integer)
+        } else if value
+            .parse::<f64>()
+            .is_ok_and(|fp| fp.is_finite() && format!("{:?}", fp) == value)
+        {
+            Some(ColumnType::Real)
+        } else {
+            Some(ColumnType::Text)
+        }
+    }
+
+    fn widen(self, other: Self) -> Self {
+        match (self, other) {
+            (ColumnType::Text, _) | (_, ColumnType::Text) => ColumnType::Text,
+            (ColumnType::Real, _) | (_, ColumnType::Real) => ColumnType::Real,
+            _ => ColumnType::Integer,
+        }
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+// if the table doesn't exist, the first row names the columns and the rows after it
+// decide their types
+pub fn import_file(
+    conn: &Connection,
+    path: &Path,
+    opts: &ImportOptions,
+    writer: &mut dyn Write,
+) -> anyhow::Result<ImportSummary> {
+    let file = File::open(path).with_context(|| format!("cannot open \"{}\"", opts.file))?;
+    let mut builder = ReaderBuilder::new();
+    builder
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(opts.col_sep)
+        .quoting(opts.quoting);
+    if let Some(row_sep) = opts.row_sep {
+        builder.terminator(Terminator::Any(row_sep));
+    }
+    let mut records = builder.from_reader(file).into_records().skip(opts.skip);
+
+    let target = match &opts.schema {
+        Some(schema) => format!(
+            "{}.{}",
+            util::quote_identifier(schema),
+            util::quote_identifier(&opts.table)
+        ),
+        None => util::quote_identifier(&opts.table),
+    };
+
+    // everything happens inside of a single transaction, if anything fails midway the
+    // transaction is rolled back when it is dropped
+    let tx = conn.unchecked_transaction()?;
+    let mut summary = ImportSummary::default();
+
+    let existing_columns = count_columns(&tx, opts)?;
+    let mut sample: Vec<StringRecord> = Vec::new();
+    // for every column, whether an empty field should be stored as NULL
+    let mut empty_as_null = Vec::new();
+
+    let col_count = match existing_columns {
+        Some(col_count) => {
+            empty_as_null.resize(col_count, false);
+            col_count
+        }
+        None => {
+            let header = match records.next() {
+                Some(header) => header?,
+                None => bail!("\"{}\" is empty, unable to create table", opts.file),
+            };
+
+            for record in records.by_ref().take(SAMPLE_ROWS) {
+                sample.push(record?);
+            }
+
+            let column_types = infer_column_types(header.len(), &sample);
+            let column_defs = column_names(&header)
+                .iter()
+                .zip(&column_types)
+                .map(|(name, ty)| format!("{} {}", util::quote_identifier(name), ty.as_sql()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let create_sql = format!("CREATE TABLE {}({})", target, column_defs);
+            if opts.verbose {
+                writeln!(writer, "{};", create_sql)?;
+            }
+            tx.execute(&create_sql, ())?;
+            summary.created = true;
+
+            empty_as_null = column_types
+                .iter()
+                .map(|ty| *ty != ColumnType::Text)
+                .collect();
+            header.len()
+        }
+    };
+
+    let placeholders = vec!["?"; col_count].join(",");
+    let insert_sql = format!("INSERT INTO {} VALUES ({})", target, placeholders);
+    if opts.verbose {
+        writeln!(writer, "{};", insert_sql)?;
+    }
+    let mut insert_stmt = tx.prepare(&insert_sql)?;
+
+    for record in sample.into_iter().map(Ok).chain(records) {
+        let record = record?;
+        let line = record.position().map(|pos| pos.line()).unwrap_or(0);
+
+        if record.len() != col_count {
+            eprintln!(
+                "{}:{}: expected {} columns but found {}, row rejected",
+                opts.file,
+                line,
+                col_count,
+                record.len()
+            );
+            summary.rejected += 1;
+            continue;
+        }
+
+        let values = record
+            .iter()
+            .zip(&empty_as_null)
+            .map(|(field, &null)| (!(null && field.is_empty())).then_some(field));
+
+        match insert_stmt.execute(params_from_iter(values)) {
+            Ok(_) => summary.inserted += 1,
+            Err(e) => {
+                eprintln!("{}:{}: {}, row rejected", opts.file, line, e);
+                summary.rejected += 1;
+            }
+        }
+    }
+
+    drop(insert_stmt);
+    tx.commit()?;
+
+    Ok(summary)
+}
+
+fn count_columns(conn: &Connection, opts: &ImportOptions) -> anyhow::Result<Option<usize>> {
+    let sql = "SELECT count(*) FROM pragma_table_info(?1, ?2) HAVING count(*) > 0";
+    let col_count = conn
+        .query_row(sql, (&opts.table, &opts.schema), |row| row.get::<_, i64>(0))
+        .optional()?;
+
+    Ok(col_count.map(|count| count as usize))
+}
+
+fn column_names(header: &StringRecord) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(header.len());
+    for (idx, name) in header.iter().enumerate() {
+        let name = name.trim();
+        let name = if name.is_empty() {
+            format!("c{}", idx + 1)
+        } else if names.iter().any(|seen| seen.eq_ignore_ascii_case(name)) {
+            format!("{}_{}", name, idx + 1)
+        } else {
+            name.to_string()
+        };
+        names.push(name);
+    }
+    names
+}
+
+fn infer_column_types(col_count: usize, sample: &[StringRecord]) -> Vec<ColumnType> {
+    (0..col_count)
+        .map(|col_idx| {
+            sample
+                .iter()
+                .filter_map(|record| record.get(col_idx).and_then(ColumnType::of))
+                .reduce(ColumnType::widen)
+                .unwrap_or(ColumnType::Text)
+        })
+        .collect()
+}
+
+fn single_byte_separator(value: &str) -> Result<u8, String> {
+    let unescaped = util::unescape(value)?;
+    match unescaped.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(format!(
+            "separator must be a single byte, got \"{}\"",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infer(rows: &[&[&str]]) -> Vec<ColumnType> {
+        let sample: Vec<StringRecord> = rows.iter().map(|row| StringRecord::from(*row)).collect();
+        infer_column_types(rows[0].len(), &sample)
+    }
+
+    #[test]
+    fn infers_the_widest_type_of_each_column() {
+        assert_eq!(
+            infer(&[&["1", "1.5", "a", "2"], &["-3", "2", "4", "2.25"]]),
+            [
+                ColumnType::Integer,
+                ColumnType::Real,
+                ColumnType::Text,
+                ColumnType::Real,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_that_would_change_stay_text() {
+        for value in ["007", "+5", "1.50", "1e3", " 4", "inf", "NaN"] {
+            assert_eq!(ColumnType::of(value), Some(ColumnType::Text), "{}", value);
+        }
+    }
+
+    #[test]
+    fn empty_values_dont_count() {
+        assert_eq!(ColumnType::of(""), None);
+        assert_eq!(ColumnType::of("  "), None);
+        assert_eq!(infer(&[&[""], &["12"]]), [ColumnType::Integer]);
+        assert_eq!(infer(&[&[""]]), [ColumnType::Text]);
+    }
+}
@@ -0,0 +1,164 @@
+use rusqlite::{Connection, InterruptHandle};
+use std::fmt;
+use std::process::exit;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static INTERRUPT_HANDLE: Mutex<Option<InterruptHandle>> = Mutex::new(None);
+// the shell gives up after a few Ctrl-C presses that don't get back to the prompt
+static SEEN_INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+const MAX_INTERRUPTS: usize = 3;
+
+// the handler only gets a plain function, so the options of `.progress` are copied here
+static PROGRESS: Mutex<Option<Progress>> = Mutex::new(None);
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProgressOptions {
+    // 0 turns the handler off
+    pub interval: i32,
+    pub quiet: bool,
+    pub reset: bool,
+    pub once: bool,
+    // 0 means no limit
+    pub limit: u64,
+}
+
+impl ProgressOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut interval = None;
+        let mut opts = Self {
+            interval: 0,
+            quiet: false,
+            reset: false,
+            once: false,
+            limit: 0,
+        };
+
+        let mut args = args.iter().filter(|arg| !arg.is_empty());
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--quiet" | "-q" => opts.quiet = true,
+                "--reset" => opts.reset = true,
+                "--once" => opts.once = true,
+                "--limit" => {
+                    let value = args.next().ok_or("--limit needs an argument")?;
+                    opts.limit = value
+                        .parse()
+                        .map_err(|_| format!("invalid value for --limit: {}", value))?;
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => {
+                    let value = arg
+                        .parse()
+                        .map_err(|_| format!("invalid value for N: {}", arg))?;
+                    interval = Some(value);
+                }
+            }
+        }
+
+        opts.interval =
+            interval.ok_or("Usage: .progress N ?--quiet? ?--reset? ?--once? ?--limit M?")?;
+        Ok(opts)
+    }
+}
+
+// the options the way `.progress` would be given them, or `off`
+impl fmt::Display for ProgressOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.interval <= 0 {
+            return f.write_str("off");
+        }
+
+        write!(f, "{}", self.interval)?;
+        for (set, option) in [
+            (self.quiet, " --quiet"),
+            (self.reset, " --reset"),
+            (self.once, " --once"),
+        ] {
+            if set {
+                f.write_str(option)?;
+            }
+        }
+        if self.limit > 0 {
+            write!(f, " --limit {}", self.limit)?;
+        }
+        Ok(())
+    }
+}
+
+struct Progress {
+    opts: ProgressOptions,
+    calls: u64,
+}
+
+pub fn install_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if SEEN_INTERRUPTS.fetch_add(1, Ordering::SeqCst) + 1 >= MAX_INTERRUPTS {
+            exit(1);
+        }
+        if let Some(handle) = INTERRUPT_HANDLE.lock().unwrap().as_ref() {
+            handle.interrupt();
+        }
+    })
+}
+
+pub fn watch(conn: &Connection) {
+    *INTERRUPT_HANDLE.lock().unwrap() = Some(conn.get_interrupt_handle());
+
+    let progress = PROGRESS
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|progress| progress.opts);
+    let _ = match progress {
+        Some(opts) if opts.interval > 0 => conn.progress_handler(opts.interval, Some(on_progress)),
+        _ => conn.progress_handler(0, None::<fn() -> bool>),
+    };
+}
+
+pub fn prompt_reached() {
+    SEEN_INTERRUPTS.store(0, Ordering::SeqCst);
+}
+
+pub fn seen_interrupt() -> bool {
+    SEEN_INTERRUPTS.load(Ordering::SeqCst) > 0
+}
+
+pub fn statement_started() {
+    if let Some(progress) = PROGRESS.lock().unwrap().as_mut()
+        && progress.opts.reset
+    {
+        progress.calls = 0;
+    }
+}
+
+pub fn set_progress(conn: &Connection, opts: ProgressOptions) {
+    *PROGRESS.lock().unwrap() = Some(Progress { opts, calls: 0 });
+    watch(conn);
+}
+
+// the reports go to stderr on purpose: they have to show up while the statement runs,
+// and a `.output` or `.once` file should only receive the results
+fn on_progress() -> bool {
+    let mut progress = PROGRESS.lock().unwrap();
+    let Some(progress) = progress.as_mut() else {
+        return false;
+    };
+
+    progress.calls += 1;
+    if progress.opts.limit > 0 && progress.calls >= progress.opts.limit {
+        eprintln!("Progress limit reached ({})", progress.calls);
+        if progress.opts.reset {
+            progress.calls = 0;
+        }
+        if progress.opts.once {
+            progress.opts.limit = 0;
+        }
+        return true;
+    }
+
+    if !progress.opts.quiet {
+        eprintln!("Progress {}", progress.calls);
+    }
+    false
+}
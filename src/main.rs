@@ -1,52 +1,115 @@
+use std::io::{IsTerminal, stdin};
 use std::process::exit;
 
 use crate::app::App;
 use crate::tui::Prompt;
 use crate::{config::Context, runner::CommandRunner};
-use clap::Parser;
 
 mod app;
+mod archive;
+mod busy;
+mod columnar;
 mod config;
 mod consts;
+mod dbinfo;
+mod dbpage;
+mod dump;
+mod expert;
+mod explain;
+mod import;
+mod interrupt;
+mod once;
+mod recover;
 mod runner;
+mod script;
+mod session;
+mod sha3sum;
+mod stats;
+mod trace;
 mod tui;
 mod util;
 
 fn main() -> anyhow::Result<()> {
-    let app = App::parse();
+    let mut app = App::parse_args();
+    let archive_args = app.archive.take();
+    let extensions = std::mem::take(&mut app.load);
+    let init_script = app.init.take();
+    let interactive = app.interactive || (!app.batch && stdin().is_terminal());
 
     let mut ctx = Context::from(app);
+    interrupt::install_handler()?;
+    interrupt::watch(&ctx.conn.borrow());
+    busy::install(&ctx.conn.borrow(), ctx.timeout);
+
+    for extension in extensions {
+        if let Err(msg) = ctx.load_extension(&extension, None) {
+            eprintln!("ERROR: {}", msg);
+            exit(1);
+        }
+    }
+
+    if let Some(script) = init_script {
+        let mut runner = CommandRunner::new(&mut ctx);
+        runner.run_dot_command(".read", &[&script]);
+        if ctx.bail && ctx.error_count > 0 {
+            exit(1);
+        }
+    }
+
+    if let Some(args) = archive_args {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        if !CommandRunner::new(&mut ctx).run_dot_command(".archive", &args) {
+            exit(1);
+        }
+        return Ok(());
+    }
+
+    // like the `sqlite3` shell, a COMMAND replaces reading from stdin
+    if let Some(command) = ctx.command.take() {
+        run_input(&mut ctx, &command);
+    } else if interactive {
+        run_interactive(&mut ctx)?;
+    } else {
+        CommandRunner::new(&mut ctx).run_script(stdin().lock(), "stdin");
+    }
+
+    if ctx.error_count > 0 {
+        exit(1);
+    }
+    Ok(())
+}
+
+fn run_interactive(ctx: &mut Context) -> anyhow::Result<()> {
     let cloned_conn = ctx.conn.clone();
 
     let mut prompt = Prompt::new(cloned_conn);
 
     loop {
+        interrupt::prompt_reached();
         let user_input = prompt.readline();
         match user_input {
             Ok(input) => {
                 prompt.add_history_entry(&input)?;
-                if input == ".quit" {
+                if !run_input(ctx, &input) {
                     break;
                 }
-
-                if input.starts_with(".exit") {
-                    let exit_code = input
-                        .split(" ")
-                        .skip(1)
-                        .take(1)
-                        .next()
-                        .unwrap_or("0")
-                        .parse::<i32>()
-                        .unwrap_or(0);
-                    exit(exit_code);
+            }
+            Err(e) => {
+                if !util::handle_readline_err(e) {
+                    break;
                 }
-
-                let mut runner = CommandRunner::new(&mut ctx);
-                runner.run_command(&input)?;
             }
-            Err(e) => util::handle_readline_err(e),
         }
     }
     prompt.save_history()?;
     Ok(())
 }
+
+fn run_input(ctx: &mut Context, input: &str) -> bool {
+    if input == ".quit" {
+        return false;
+    }
+
+    let mut runner = CommandRunner::new(ctx);
+    runner.run_command(input) || !ctx.bail
+}
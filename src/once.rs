@@ -0,0 +1,161 @@
+use crate::config::{Output, TableMode};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub enum OnceTarget {
+    File(String),
+    Editor,
+    Spreadsheet,
+}
+
+impl OnceTarget {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut target = None;
+
+        for &arg in args.iter().filter(|arg| !arg.is_empty()) {
+            let next = match arg {
+                "-e" => OnceTarget::Editor,
+                "-x" => OnceTarget::Spreadsheet,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => OnceTarget::File(arg.to_string()),
+            };
+            if target.replace(next).is_some() {
+                return Err("Usage: .once ?-e|-x? ?FILE?".to_string());
+            }
+        }
+
+        target.ok_or_else(|| "Usage: .once ?-e|-x? ?FILE?".to_string())
+    }
+}
+
+pub struct OnceRedirect {
+    pub previous_output: Output,
+    pub previous_mode: TableMode,
+    pub previous_header: bool,
+    pub open_with: Option<(String, PathBuf)>,
+}
+
+// files for the editor and the spreadsheet are left in the temporary directory for the
+// program that opens them
+pub fn create_output(target: &OnceTarget, cwd: &Path) -> Result<(Output, PathBuf), String> {
+    let path = match target {
+        OnceTarget::File(file) => cwd.join(file),
+        OnceTarget::Editor => temp_path("txt"),
+        OnceTarget::Spreadsheet => temp_path("csv"),
+    };
+
+    let file =
+        File::create(&path).map_err(|e| format!("cannot open \"{}\": {}", path.display(), e))?;
+    Ok((
+        Output::BufferedFile(BufWriter::new(file), path.clone()),
+        path,
+    ))
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    std::env::temp_dir().join(format!(
+        "shqlite-{}-{}.{}",
+        std::process::id(),
+        nanos,
+        extension
+    ))
+}
+
+pub fn editor() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string())
+}
+
+pub fn default_launcher() -> String {
+    let launcher = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "cmd /c start"
+    } else {
+        "xdg-open"
+    };
+    launcher.to_string()
+}
+
+// `program` may carry arguments of its own, e.g. `code --wait`
+pub fn open_file(program: &str, file: &Path) -> Result<(), String> {
+    let mut words = program.split_whitespace();
+    let name = words.next().ok_or("no program to open the output with")?;
+
+    let status = Command::new(name)
+        .args(words)
+        .arg(file)
+        .status()
+        .map_err(|e| format!("unable to run \"{}\": {}", program, e))?;
+    if !status.success() {
+        return Err(format!("\"{}\" failed with {}", program, status));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_target() {
+        assert!(matches!(OnceTarget::parse(&["-e"]), Ok(OnceTarget::Editor)));
+        assert!(matches!(
+            OnceTarget::parse(&["", "-x"]),
+            Ok(OnceTarget::Spreadsheet)
+        ));
+        assert!(matches!(
+            OnceTarget::parse(&["out.txt"]),
+            Ok(OnceTarget::File(file)) if file == "out.txt"
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_unknown_and_extra_arguments() {
+        assert!(OnceTarget::parse(&[]).is_err());
+        assert!(OnceTarget::parse(&["--bogus"]).is_err());
+        assert!(OnceTarget::parse(&["-e", "-x"]).is_err());
+        assert!(OnceTarget::parse(&["a.txt", "b.txt"]).is_err());
+    }
+
+    // `touch` stands in for the launcher, the file it creates shows what it was given
+    #[cfg(unix)]
+    #[test]
+    fn open_file_runs_the_launcher_with_the_file() {
+        let file = temp_path("txt");
+        open_file("touch", &file).unwrap();
+        assert!(file.exists());
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_file_passes_the_arguments_of_the_launcher() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = temp_path("txt");
+        std::fs::write(&file, "").unwrap();
+        open_file("chmod 600", &file).unwrap();
+        let mode = std::fs::metadata(&file).unwrap().permissions().mode();
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_file_reports_a_failing_launcher() {
+        let file = temp_path("txt");
+        assert!(open_file("false", &file).is_err());
+        assert!(open_file("shqlite-no-such-launcher", &file).is_err());
+        assert!(open_file("  ", &file).is_err());
+    }
+}
@@ -0,0 +1,700 @@
+use crate::{dbpage, util};
+use rusqlite::Connection;
+use rusqlite::types::{Value, ValueRef};
+use std::collections::HashSet;
+use std::io::Write;
+
+const HEADER_MAGIC: &[u8] = b"SQLite format 3\0";
+const HEADER_SIZE: usize = 100;
+
+const INTERIOR_INDEX: u8 = 0x02;
+const INTERIOR_TABLE: u8 = 0x05;
+const LEAF_INDEX: u8 = 0x0A;
+const LEAF_TABLE: u8 = 0x0D;
+
+#[derive(Debug)]
+pub struct RecoverOptions {
+    pub lost_and_found: String,
+    pub ignore_freelist: bool,
+}
+
+impl RecoverOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut opts = Self {
+            lost_and_found: "lost_and_found".to_string(),
+            ignore_freelist: false,
+        };
+
+        let mut args = args.iter().filter(|arg| !arg.is_empty());
+        while let Some(&arg) = args.next() {
+            match arg {
+                "--ignore-freelist" => opts.ignore_freelist = true,
+                "--lost-and-found" => {
+                    let value = args.next().ok_or("--lost-and-found needs an argument")?;
+                    opts.lost_and_found = value.to_string();
+                }
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+struct Record {
+    pgno: u32,
+    // `None` for the rows of `WITHOUT ROWID` tables
+    rowid: Option<i64>,
+    values: Vec<Value>,
+}
+
+// corrupt pages make the accessors return `None` instead of panicking
+struct Pager {
+    data: Vec<u8>,
+    page_size: usize,
+    usable_size: usize,
+    page_count: u32,
+    encoding: TextEncoding,
+}
+
+impl Pager {
+    fn new(data: Vec<u8>) -> Self {
+        let valid_header = data.starts_with(HEADER_MAGIC) && data.len() >= HEADER_SIZE;
+
+        let page_size = match valid_header.then(|| u16::from_be_bytes([data[16], data[17]])) {
+            Some(1) => 65536,
+            Some(size) if size >= 512 && size.is_power_of_two() => size as usize,
+            // the header is damaged, most databases use the default page size
+            _ => 4096,
+        };
+        let reserved = if valid_header { data[20] as usize } else { 0 };
+        let encoding = match valid_header.then(|| data[59]) {
+            Some(2) => TextEncoding::Utf16Le,
+            Some(3) => TextEncoding::Utf16Be,
+            _ => TextEncoding::Utf8,
+        };
+        let page_count = data.len().div_ceil(page_size) as u32;
+
+        Self {
+            data,
+            page_size,
+            usable_size: page_size - reserved,
+            page_count,
+            encoding,
+        }
+    }
+
+    fn page(&self, pgno: u32) -> Option<&[u8]> {
+        if pgno == 0 || pgno > self.page_count {
+            return None;
+        }
+        let start = (pgno as usize - 1) * self.page_size;
+        let end = (start + self.page_size).min(self.data.len());
+        self.data.get(start..end)
+    }
+
+    fn header_offset(pgno: u32) -> usize {
+        if pgno == 1 { HEADER_SIZE } else { 0 }
+    }
+
+    fn page_type(&self, pgno: u32) -> Option<u8> {
+        self.page(pgno)?
+            .get(Self::header_offset(pgno))
+            .copied()
+            .filter(|ty| {
+                matches!(
+                    *ty,
+                    INTERIOR_INDEX | INTERIOR_TABLE | LEAF_INDEX | LEAF_TABLE
+                )
+            })
+    }
+
+    fn cell_offsets(&self, pgno: u32) -> Option<Vec<usize>> {
+        let page = self.page(pgno)?;
+        let header = Self::header_offset(pgno);
+        let page_type = self.page_type(pgno)?;
+        let cell_count = read_u16(page, header + 3)? as usize;
+        let pointers = header + if is_interior(page_type) { 12 } else { 8 };
+
+        (0..cell_count)
+            .map(|idx| read_u16(page, pointers + idx * 2).map(usize::from))
+            .filter(|offset| offset.is_none_or(|offset| offset < page.len()))
+            .collect()
+    }
+
+    fn children(&self, pgno: u32) -> Vec<u32> {
+        let Some(page) = self.page(pgno) else {
+            return Vec::new();
+        };
+        let header = Self::header_offset(pgno);
+        let mut children: Vec<u32> = self
+            .cell_offsets(pgno)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|offset| read_u32(page, offset))
+            .collect();
+        children.extend(read_u32(page, header + 8));
+        children
+    }
+
+    fn payload(
+        &self,
+        pgno: u32,
+        offset: usize,
+        size: usize,
+        is_table: bool,
+        visited: &mut HashSet<u32>,
+    ) -> Option<Vec<u8>> {
+        let page = self.page(pgno)?;
+        let usable = self.usable_size;
+        let max_local = if is_table {
+            usable - 35
+        } else {
+            (usable - 12) * 64 / 255 - 23
+        };
+        let min_local = (usable - 12) * 32 / 255 - 23;
+
+        let local = if size <= max_local {
+            size
+        } else {
+            let surplus = min_local + (size - min_local) % (usable - 4);
+            if surplus <= max_local {
+                surplus
+            } else {
+                min_local
+            }
+        };
+
+        let mut payload = page.get(offset..offset + local)?.to_vec();
+        let mut next = if local < size {
+            read_u32(page, offset + local)?
+        } else {
+            0
+        };
+
+        while payload.len() < size && next != 0 {
+            // a cycle in the overflow chain means that the page is corrupt
+            if !visited.insert(next) {
+                return None;
+            }
+            let overflow = self.page(next)?;
+            let take = (size - payload.len()).min(usable - 4);
+            payload.extend_from_slice(overflow.get(4..4 + take)?);
+            next = read_u32(overflow, 0)?;
+        }
+
+        (payload.len() == size).then_some(payload)
+    }
+
+    fn leaf_records(&self, pgno: u32, visited: &mut HashSet<u32>) -> Vec<Record> {
+        let (Some(page), Some(page_type)) = (self.page(pgno), self.page_type(pgno)) else {
+            return Vec::new();
+        };
+        let is_table = page_type == LEAF_TABLE;
+        let mut records = Vec::new();
+
+        for offset in self.cell_offsets(pgno).unwrap_or_default() {
+            let Some((size, len)) = read_varint(page, offset) else {
+                continue;
+            };
+            let mut cursor = offset + len;
+            let rowid = if is_table {
+                let Some((rowid, len)) = read_varint(page, cursor) else {
+                    continue;
+                };
+                cursor += len;
+                Some(rowid as i64)
+            } else {
+                None
+            };
+
+            let record = self
+                .payload(pgno, cursor, size as usize, is_table, visited)
+                .and_then(|payload| self.decode_record(&payload));
+            if let Some(values) = record {
+                records.push(Record {
+                    pgno,
+                    rowid,
+                    values,
+                });
+            }
+        }
+
+        records
+    }
+
+    fn decode_record(&self, payload: &[u8]) -> Option<Vec<Value>> {
+        let (header_size, mut cursor) = read_varint(payload, 0)?;
+        let header_size = header_size as usize;
+        if header_size > payload.len() {
+            return None;
+        }
+
+        let mut serial_types = Vec::new();
+        while cursor < header_size {
+            let (serial_type, len) = read_varint(payload, cursor)?;
+            serial_types.push(serial_type);
+            cursor += len;
+        }
+
+        let mut body = header_size;
+        let mut values = Vec::with_capacity(serial_types.len());
+        for serial_type in serial_types {
+            let (value, len) = self.decode_value(payload, body, serial_type)?;
+            values.push(value);
+            body += len;
+        }
+
+        Some(values)
+    }
+
+    fn decode_value(&self, payload: &[u8], at: usize, serial_type: u64) -> Option<(Value, usize)> {
+        let int = |len: usize| {
+            let bytes = payload.get(at..at + len)?;
+            // sign extend the big-endian two's complement integer
+            let first = if bytes[0] & 0x80 != 0 { -1i64 } else { 0 };
+            let value = bytes
+                .iter()
+                .fold(first, |acc, byte| (acc << 8) | *byte as i64);
+            Some((Value::Integer(value), len))
+        };
+
+        match serial_type {
+            0 => Some((Value::Null, 0)),
+            1..=4 => int(serial_type as usize),
+            5 => int(6),
+            6 => int(8),
+            7 => {
+                let bytes: [u8; 8] = payload.get(at..at + 8)?.try_into().ok()?;
+                Some((Value::Real(f64::from_be_bytes(bytes)), 8))
+            }
+            8 => Some((Value::Integer(0), 0)),
+            9 => Some((Value::Integer(1), 0)),
+            _ if serial_type >= 12 && serial_type.is_multiple_of(2) => {
+                let len = (serial_type as usize - 12) / 2;
+                Some((Value::Blob(payload.get(at..at + len)?.to_vec()), len))
+            }
+            _ if serial_type >= 13 => {
+                let len = (serial_type as usize - 13) / 2;
+                let bytes = payload.get(at..at + len)?;
+                Some((Value::Text(self.decode_text(bytes)), len))
+            }
+            // serial types 10 and 11 are reserved
+            _ => None,
+        }
+    }
+
+    fn decode_text(&self, bytes: &[u8]) -> String {
+        let utf16 = |to_u16: fn([u8; 2]) -> u16| {
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| to_u16([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        };
+
+        match self.encoding {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).to_string(),
+            TextEncoding::Utf16Le => utf16(u16::from_le_bytes),
+            TextEncoding::Utf16Be => utf16(u16::from_be_bytes),
+        }
+    }
+
+    fn walk_tree(&self, root: u32, visited: &mut HashSet<u32>) -> Vec<Record> {
+        let mut records = Vec::new();
+        let mut pending = vec![root];
+
+        while let Some(pgno) = pending.pop() {
+            // pages that were already seen are either shared by accident or part of a cycle
+            if !visited.insert(pgno) {
+                continue;
+            }
+            match self.page_type(pgno) {
+                Some(INTERIOR_TABLE | INTERIOR_INDEX) => {
+                    // children are pushed in reverse so that the rows come out in order
+                    pending.extend(self.children(pgno).into_iter().rev());
+                }
+                Some(LEAF_TABLE | LEAF_INDEX) => records.extend(self.leaf_records(pgno, visited)),
+                _ => {}
+            }
+        }
+
+        records
+    }
+
+    fn freelist_pages(&self) -> HashSet<u32> {
+        let mut pages = HashSet::new();
+        let mut trunk = self
+            .data
+            .starts_with(HEADER_MAGIC)
+            .then(|| read_u32(&self.data, 32))
+            .flatten()
+            .unwrap_or(0);
+
+        while trunk != 0 && pages.insert(trunk) {
+            let Some(page) = self.page(trunk) else {
+                break;
+            };
+            let leaf_count = read_u32(page, 4).unwrap_or(0) as usize;
+            for idx in 0..leaf_count.min(self.usable_size / 4) {
+                pages.extend(read_u32(page, 8 + idx * 4));
+            }
+            trunk = read_u32(page, 0).unwrap_or(0);
+        }
+
+        pages
+    }
+}
+
+fn is_interior(page_type: u8) -> bool {
+    matches!(page_type, INTERIOR_INDEX | INTERIOR_TABLE)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_varint(data: &[u8], at: usize) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for idx in 0..9 {
+        let byte = *data.get(at + idx)?;
+        if idx == 8 {
+            return Some(((value << 8) | byte as u64, 9));
+        }
+        value = (value << 7) | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
+
+struct SchemaEntry {
+    kind: String,
+    name: String,
+    rootpage: u32,
+    sql: String,
+}
+
+struct TableLayout {
+    stored: Vec<String>,
+    // stored generated columns, their values are computed again when the row is inserted
+    generated: Vec<bool>,
+    // the stored value of the `INTEGER PRIMARY KEY` column is always NULL
+    ipk: Option<usize>,
+    keep_rowid: bool,
+}
+
+impl TableLayout {
+    // the layout comes from creating the table in `scratch` and reading it back
+    fn new(scratch: &Connection, entry: &SchemaEntry) -> Option<Self> {
+        scratch.execute(&entry.sql, ()).ok()?;
+
+        let without_rowid = scratch
+            .query_row(
+                "SELECT wr FROM pragma_table_list WHERE schema = 'main' AND name = ?1",
+                [&entry.name],
+                |row| row.get::<_, bool>(0),
+            )
+            .ok()?;
+
+        // virtual generated columns are computed on the fly and never stored, stored ones
+        // (hidden = 3) take up a field of the record
+        let mut stmt = scratch
+            .prepare("SELECT name, pk, upper(type), hidden = 3 FROM pragma_table_xinfo(?1) WHERE hidden != 2")
+            .ok()?;
+        let columns = stmt
+            .query_map([&entry.name], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            })
+            .ok()?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .ok()?;
+
+        let pk_count = columns.iter().filter(|(_, pk, _, _)| *pk > 0).count();
+        let ipk = (!without_rowid && pk_count == 1)
+            .then(|| {
+                columns
+                    .iter()
+                    .position(|(_, pk, ty, _)| *pk == 1 && ty == "INTEGER")
+            })
+            .flatten();
+
+        let mut ordered: Vec<_> = columns.iter().collect();
+        if without_rowid {
+            // the primary key comes first in the records of `WITHOUT ROWID` tables
+            let mut pk: Vec<_> = columns.iter().filter(|(_, pk, _, _)| *pk > 0).collect();
+            pk.sort_by_key(|(_, pk, _, _)| *pk);
+            ordered = pk
+                .into_iter()
+                .chain(columns.iter().filter(|(_, pk, _, _)| *pk == 0))
+                .collect();
+        }
+
+        Some(Self {
+            stored: ordered.iter().map(|(name, _, _, _)| name.clone()).collect(),
+            generated: ordered
+                .iter()
+                .map(|(_, _, _, generated)| *generated)
+                .collect(),
+            ipk,
+            keep_rowid: !without_rowid && ipk.is_none(),
+        })
+    }
+}
+
+pub fn recover_database(
+    conn: &Connection,
+    writer: &mut dyn Write,
+    opts: &RecoverOptions,
+) -> anyhow::Result<()> {
+    if conn.path().is_none_or(str::is_empty) {
+        anyhow::bail!("the main database is not a file, there is nothing to recover");
+    }
+    // the pages that are still in the write-ahead log count as well
+    let pager = Pager::new(dbpage::read_pages(conn, "main", false)?);
+
+    let mut visited = HashSet::new();
+    if opts.ignore_freelist {
+        visited.extend(pager.freelist_pages());
+    }
+
+    let schema: Vec<SchemaEntry> = pager
+        .walk_tree(1, &mut visited)
+        .into_iter()
+        .filter_map(|record| match &record.values[..] {
+            [Value::Text(kind), Value::Text(name), _, rootpage, sql] => Some(SchemaEntry {
+                kind: kind.clone(),
+                name: name.clone(),
+                rootpage: match rootpage {
+                    Value::Integer(page) => *page as u32,
+                    _ => 0,
+                },
+                sql: match sql {
+                    Value::Text(sql) => sql.clone(),
+                    _ => String::new(),
+                },
+            }),
+            _ => None,
+        })
+        .collect();
+
+    // the layouts are taken from an empty copy of every table that can still be created
+    let scratch = Connection::open_in_memory()?;
+    let _ = writeln!(writer, "BEGIN;");
+
+    let mut writable_schema = false;
+    let mut lost: Vec<(u32, Record)> = Vec::new();
+
+    // `sqlite_sequence` goes last, restoring AUTOINCREMENT tables adds rows to it
+    let mut tables: Vec<_> = schema
+        .iter()
+        .filter(|entry| entry.kind == "table")
+        .collect();
+    tables.sort_by_key(|entry| entry.name == "sqlite_sequence");
+
+    for entry in tables {
+        let is_virtual = entry.sql.starts_with("CREATE VIRTUAL TABLE");
+        if is_virtual {
+            if !writable_schema {
+                let _ = writeln!(writer, "PRAGMA writable_schema = on;");
+                writable_schema = true;
+            }
+            let _ = writeln!(
+                writer,
+                "INSERT INTO sqlite_schema(type,name,tbl_name,rootpage,sql)VALUES('table',{},{},0,{});",
+                util::quote_string_literal(&entry.name),
+                util::quote_string_literal(&entry.name),
+                util::quote_string_literal(&entry.sql)
+            );
+            continue;
+        }
+
+        let is_internal = entry.name.starts_with("sqlite_");
+        if is_internal && entry.name != "sqlite_sequence" {
+            continue;
+        }
+        if is_internal {
+            let _ = writeln!(writer, "DELETE FROM sqlite_sequence;");
+        } else if !entry.sql.is_empty() {
+            let _ = writeln!(writer, "{};", entry.sql);
+        }
+
+        let layout = if is_internal {
+            // `sqlite_sequence` can't be created directly, it comes with AUTOINCREMENT tables
+            Some(TableLayout {
+                stored: vec!["name".to_string(), "seq".to_string()],
+                generated: vec![false, false],
+                ipk: None,
+                keep_rowid: false,
+            })
+        } else {
+            TableLayout::new(&scratch, entry)
+        };
+
+        let records = pager.walk_tree(entry.rootpage, &mut visited);
+        let table = util::quote_identifier_if_needed(&entry.name);
+        for record in records {
+            match &layout {
+                Some(layout) if record.values.len() <= layout.stored.len() => {
+                    let _ = writeln!(writer, "{}", insert_statement(&table, layout, &record));
+                }
+                // rows that don't fit the table are kept anyway
+                _ => lost.push((entry.rootpage, record)),
+            }
+        }
+    }
+
+    // pages that no table refers to, including freed ones, may still hold rows. Trees are
+    // walked from their topmost interior page so the rows keep their root page number
+    let unvisited: Vec<u32> = (1..=pager.page_count)
+        .filter(|pgno| !visited.contains(pgno))
+        .collect();
+    let referenced: HashSet<u32> = unvisited
+        .iter()
+        .filter(|pgno| pager.page_type(**pgno) == Some(INTERIOR_TABLE))
+        .flat_map(|pgno| pager.children(*pgno))
+        .collect();
+    for pgno in unvisited {
+        let is_table_page = matches!(pager.page_type(pgno), Some(INTERIOR_TABLE | LEAF_TABLE));
+        if is_table_page && !referenced.contains(&pgno) {
+            for record in pager.walk_tree(pgno, &mut visited) {
+                lost.push((pgno, record));
+            }
+        }
+    }
+
+    if !lost.is_empty() {
+        let name = free_table_name(&schema, &opts.lost_and_found);
+        write_lost_and_found(writer, &name, &mut lost);
+    }
+
+    for entry in schema
+        .iter()
+        .filter(|entry| matches!(entry.kind.as_str(), "index" | "trigger" | "view"))
+        .filter(|entry| !entry.sql.is_empty())
+    {
+        let _ = writeln!(writer, "{};", entry.sql);
+    }
+
+    if writable_schema {
+        let _ = writeln!(writer, "PRAGMA writable_schema = off;");
+    }
+    let _ = writeln!(writer, "COMMIT;");
+    writer.flush().expect("unable to flush");
+
+    Ok(())
+}
+
+fn insert_statement(table: &str, layout: &TableLayout, record: &Record) -> String {
+    let mut columns: Vec<String> = Vec::with_capacity(record.values.len() + 1);
+    let mut values: Vec<String> = Vec::with_capacity(record.values.len() + 1);
+
+    if let Some(rowid) = record.rowid
+        && layout.keep_rowid
+    {
+        columns.push("rowid".to_string());
+        values.push(rowid.to_string());
+    }
+
+    for (idx, (name, value)) in layout.stored.iter().zip(&record.values).enumerate() {
+        if layout.generated[idx] {
+            continue;
+        }
+        columns.push(util::quote_identifier_if_needed(name));
+        let value = match (layout.ipk == Some(idx), record.rowid) {
+            // the stored value of an `INTEGER PRIMARY KEY` is a placeholder for the rowid
+            (true, Some(rowid)) => Value::Integer(rowid),
+            _ => value.clone(),
+        };
+        values.push(util::sql_literal(ValueRef::from(&value), false));
+    }
+
+    format!(
+        "INSERT INTO {}({}) VALUES({});",
+        table,
+        columns.join(","),
+        values.join(",")
+    )
+}
+
+fn free_table_name(schema: &[SchemaEntry], name: &str) -> String {
+    let is_taken = |candidate: &str| {
+        schema
+            .iter()
+            .any(|entry| entry.name.eq_ignore_ascii_case(candidate))
+    };
+    if !is_taken(name) {
+        return name.to_string();
+    }
+
+    (0..)
+        .map(|idx| format!("{}_{}", name, idx))
+        .find(|candidate| !is_taken(candidate))
+        .expect("there is always a free name")
+}
+
+fn write_lost_and_found(writer: &mut dyn Write, name: &str, lost: &mut [(u32, Record)]) {
+    let max_fields = lost
+        .iter()
+        .map(|(_, record)| record.values.len())
+        .max()
+        .unwrap_or(0);
+    let value_columns: Vec<String> = (0..max_fields).map(|idx| format!("c{}", idx)).collect();
+    let table = util::quote_identifier_if_needed(name);
+
+    let mut definition = vec![
+        "rootpgno INTEGER".to_string(),
+        "pgno INTEGER".to_string(),
+        "nfield INTEGER".to_string(),
+        "id INTEGER".to_string(),
+    ];
+    definition.extend(value_columns.iter().cloned());
+    let _ = writeln!(writer, "CREATE TABLE {}({});", table, definition.join(", "));
+
+    // the rows are written in the order of the pages they came from
+    lost.sort_by_key(|(_, record)| record.pgno);
+
+    for (root, record) in lost.iter() {
+        let mut values = vec![
+            root.to_string(),
+            record.pgno.to_string(),
+            record.values.len().to_string(),
+            record.rowid.map_or("NULL".to_string(), |id| id.to_string()),
+        ];
+        values.extend(
+            record
+                .values
+                .iter()
+                .map(|value| util::sql_literal(ValueRef::from(value), false)),
+        );
+        let columns = ["rootpgno", "pgno", "nfield", "id"]
+            .into_iter()
+            .map(str::to_string)
+            .chain(value_columns.iter().take(record.values.len()).cloned())
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            writer,
+            "INSERT INTO {}({}) VALUES({});",
+            table,
+            columns.join(","),
+            values.join(",")
+        );
+    }
+}
@@ -1,33 +1,74 @@
-use crate::config::{Context, Output, TableMode};
-use crate::util;
+use crate::archive::{self, ArchiveOptions};
+use crate::busy::{self, TimeoutOptions};
+use crate::config::{Context, EqpMode, ExplainMode, Output, StatsMode, TableMode};
+use crate::consts::SQLITE_LIMITS;
+use crate::dump::{self, DumpOptions};
+use crate::import::{self, ImportOptions};
+use crate::interrupt::{self, ProgressOptions};
+use crate::once::{self, OnceRedirect, OnceTarget};
+use crate::recover::{self, RecoverOptions};
+use crate::script::ScriptReader;
+use crate::session::{self, ConflictPolicy, Session};
+use crate::sha3sum::{self, Sha3sumOptions};
+use crate::trace::{self, TraceOptions};
+use crate::util::{self, Stopwatch, Timing};
+use crate::{dbinfo, expert, explain, stats};
 use prettytable::format::TableFormat;
 use prettytable::{Table, row, table};
 use rusqlite::Error as RSQE;
 use rusqlite::config::DbConfig;
 use rusqlite::ffi::{SQLITE_SOURCE_ID, SQLITE_VERSION};
-use rusqlite::{Connection, MAIN_DB};
+use rusqlite::limits::Limit;
+use rusqlite::{Connection, MAIN_DB, Statement, params_from_iter};
 use std::fs::File;
 use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::{Path, PathBuf};
-use std::process::{Command};
+use std::path::PathBuf;
+use std::process::{Command, exit};
+use std::rc::Rc;
+
+// usage errors print how to use the command, failures are reported as errors
+enum DotError {
+    Usage(String),
+    Failed(String),
+}
+
+impl From<rusqlite::Error> for DotError {
+    fn from(e: rusqlite::Error) -> Self {
+        DotError::Failed(e.to_string())
+    }
+}
+
+type DotResult = Result<(), DotError>;
 
 pub struct CommandRunner<'a> {
     ctx: &'a mut Context,
+    total_time: Timing,
+    location: Option<String>,
 }
 
 impl<'a> CommandRunner<'a> {
     pub fn new(ctx: &'a mut Context) -> Self {
-        Self { ctx }
+        Self {
+            ctx,
+            total_time: Timing::default(),
+            location: None,
+        }
     }
 
-    pub fn run_command(&mut self, input: &str) -> rusqlite::Result<()> {
+    // the statements that follow a failed one are skipped after an interrupt or with `.bail on`
+    pub fn run_command(&mut self, input: &str) -> bool {
+        let errors = self.ctx.error_count;
+        // a `.once` that is already pending applies to this command only
+        let once_pending = self.ctx.once.is_some();
+
         if self.ctx.with_echo {
             let writer: &mut dyn Write = match &mut self.ctx.output {
                 Output::BufferedStdout(out) => out,
-                Output::BufferedFile(f) => f,
+                Output::BufferedFile(f, _) => f,
             };
 
-            let _ = write!(writer, "{}", input);
+            let _ = writeln!(writer, "{}", input);
+            let _ = writer.flush();
         }
 
         if input.starts_with(".") {
@@ -36,15 +77,105 @@ impl<'a> CommandRunner<'a> {
             let dot_cmd_args = &splitted[1..];
             self.run_dot_command(dot_cmd, dot_cmd_args);
         } else {
-            self.run_user_query(input)?;
+            for statement in util::split_statements(input) {
+                if let Err(e) = self.run_statement(statement)
+                    && (self.ctx.bail || util::is_interrupted(&e))
+                {
+                    break;
+                }
+            }
         }
 
-        Ok(())
+        if once_pending {
+            self.finish_once();
+        }
+
+        self.ctx.error_count == errors
     }
 
-    fn run_dot_command(&mut self, dot_cmd: &str, args: &[&str]) {
-        match dot_cmd {
-            ".archive" => self.dot_archive(args),
+    fn finish_once(&mut self) {
+        let Some(redirect) = self.ctx.once.take() else {
+            return;
+        };
+
+        self.ctx.output.flush();
+        self.ctx.output = redirect.previous_output;
+        self.ctx.mode = redirect.previous_mode;
+        self.ctx.with_header = redirect.previous_header;
+
+        if let Some((program, file)) = redirect.open_with
+            && let Err(msg) = once::open_file(&program, &file)
+        {
+            eprintln!("ERROR: {}", msg);
+            self.ctx.error_count += 1;
+        }
+    }
+
+    fn run_statement(&mut self, sql: &str) -> rusqlite::Result<()> {
+        let result = self.run_user_query(sql);
+        self.write_trace();
+        self.report_lock_wait();
+        if let Err(e) = &result {
+            if util::is_interrupted(e) {
+                eprintln!("Interrupted");
+            } else {
+                report_error(e, self.location.as_deref());
+            }
+            self.ctx.error_count += 1;
+        }
+
+        result
+    }
+
+    pub fn run_script(&mut self, reader: impl BufRead, name: &str) {
+        let outer_location = self.location.take();
+        // keep the time of the statements before this script apart from the script itself
+        let outer_time = std::mem::take(&mut self.total_time);
+        let mut statements = 0;
+
+        for entry in ScriptReader::new(reader) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("ERROR: unable to read {}: {}", name, e);
+                    self.ctx.error_count += 1;
+                    break;
+                }
+            };
+            if entry.text == ".quit" {
+                break;
+            }
+            if !entry.text.starts_with('.') {
+                statements += 1;
+            }
+
+            self.location = Some(format!("{}:{}", name, entry.line));
+            let succeeded = self.run_command(&entry.text);
+            // an interrupt stops the rest of the script as well
+            if !succeeded && (self.ctx.bail || interrupt::seen_interrupt()) {
+                break;
+            }
+        }
+
+        if self.ctx.with_timer && statements > 1 {
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            let _ = writeln!(
+                writer,
+                "Total Run Time ({} statements): {}",
+                statements, self.total_time
+            );
+            writer.flush().expect("unable to flush");
+        }
+        self.total_time.add(outer_time);
+        self.location = outer_location;
+    }
+
+    pub fn run_dot_command(&mut self, dot_cmd: &str, args: &[&str]) -> bool {
+        let result = match dot_cmd {
+            ".archive" | ".ar" => self.dot_archive(args),
             ".auth" => self.dot_auth(args),
             ".bail" => self.dot_bail(args),
             ".cd" => self.dot_cd(args),
@@ -53,12 +184,13 @@ impl<'a> CommandRunner<'a> {
             ".clone" => self.dot_clone(args),
             ".connection" => self.dot_connection(args),
             ".crlf" => self.dot_crlf(args),
-            ".databases" => self.dot_databases(args).unwrap(),
+            ".databases" => self.dot_databases(args),
             ".dbconfig" => self.dot_dbconfig(args),
             ".dbinfo" => self.dot_dbinfo(args),
             ".dbtotxt" => self.dot_dbtotxt(args),
             ".dump" => self.dot_dump(args),
             ".echo" => self.dot_echo(args),
+            ".exit" => self.dot_exit(args),
             ".eqp" => self.dot_eqp(args),
             ".excel" => self.dot_excel(args),
             ".expert" => self.dot_expert(args),
@@ -69,7 +201,7 @@ impl<'a> CommandRunner<'a> {
             ".help" => self.dot_help(args),
             ".import" => self.dot_import(args),
             ".imposter" => self.dot_imposter(args),
-            ".indexes" => self.dot_indexes(args).unwrap(),
+            ".indexes" => self.dot_indexes(args),
             ".intck" => self.dot_intck(args),
             ".limit" => self.dot_limit(args),
             ".lint" => self.dot_lint(args),
@@ -90,14 +222,14 @@ impl<'a> CommandRunner<'a> {
             ".restore" => self.dot_restore(args),
             ".save" | ".backup" => self.dot_save(args),
             ".scanstats" => self.dot_scanstats(args),
-            ".schema" => self.dot_schema(args).unwrap(),
+            ".schema" => self.dot_schema(args),
             ".separator" => self.dot_separator(args),
             ".session" => self.dot_session(args),
             ".sha3sum" => self.dot_sha3sum(args),
             ".show" => self.dot_show(args),
             ".stats" => self.dot_stats(args),
             ".system" | ".shell" => self.dot_system(args),
-            ".tables" => self.dot_tables(args).unwrap(),
+            ".tables" => self.dot_tables(args),
             ".timeout" => self.dot_timeout(args),
             ".timer" => self.dot_timer(args),
             ".trace" => self.dot_trace(args),
@@ -108,85 +240,293 @@ impl<'a> CommandRunner<'a> {
             ".vfsname" => self.dot_vfsname(args),
             ".width" => self.dot_width(args),
             ".www" => self.dot_www(args),
-            _ => eprintln!(
-                "Error: unknown command or invalid arguments:  \"{}\". Enter \".help\" for help",
+            _ => Err(DotError::Failed(format!(
+                "unknown command or invalid arguments:  \"{}\". Enter \".help\" for help",
                 dot_cmd
-            ),
+            ))),
+        };
+
+        // dot commands run statements of their own, e.g. `.tables`
+        self.write_trace();
+        match result {
+            Ok(()) => return true,
+            Err(DotError::Usage(msg)) => println!("{}", msg),
+            Err(DotError::Failed(msg)) => match &self.location {
+                Some(location) => eprintln!("ERROR: {}: {}", location, msg),
+                None => eprintln!("ERROR: {}", msg),
+            },
         }
+        self.ctx.error_count += 1;
+        false
     }
 
     fn run_user_query(&mut self, query: &str) -> rusqlite::Result<()> {
-        match self.ctx.conn.borrow().prepare(query) {
-            Ok(mut stmt) => {
-                let col_count = stmt.column_count();
+        // with `.expert` the statement is only analyzed, it doesn't run
+        if self.ctx.expert {
+            self.ctx.expert = false;
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            return expert::suggest_indexes(&self.ctx.conn.borrow(), query, writer);
+        }
 
-                if col_count == 0 {
-                    stmt.execute(())?;
-                    return Ok(());
-                }
+        interrupt::statement_started();
+        busy::statement_started();
 
-                let column_names = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
-                let row_datas = util::query_data_rows(
-                    &mut stmt,
-                    col_count,
-                    self.ctx.mode,
-                    self.ctx.null_value_repr.as_ref(),
-                )?;
-
-                util::construct_and_print_output(
-                    &mut self.ctx.output,
-                    self.ctx.mode,
-                    column_names,
-                    row_datas,
-                    self.ctx.with_header,
-                );
+        // a handle of our own keeps `self` free to be borrowed while the statement is alive
+        let conn = Rc::clone(&self.ctx.conn);
+        let conn = conn.borrow();
+        // the timer covers preparing and stepping the statement, but not printing its rows
+        let stopwatch = Stopwatch::start();
+        let mut stmt = conn.prepare(query)?;
+        let col_count = stmt.column_count();
+        // bind every parameter from the values stored with `.parameter`
+        let params = util::lookup_parameters(&conn, &stmt)?;
+
+        // statements that are already an `EXPLAIN` are rendered instead of tabulated
+        let explain_kind = stmt.is_explain();
+        if explain_kind > 0 && self.ctx.explain != ExplainMode::Off {
+            self.write_trace();
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            if explain_kind == 1 {
+                explain::print_bytecode(writer, &mut stmt, params_from_iter(params))?;
+            } else {
+                explain::print_query_plan(writer, &mut stmt, params_from_iter(params))?;
             }
-            Err(e) => match e {
-                RSQE::SqlInputError { msg, sql, .. } => {
-                    eprintln!("ERROR: {} is in invalid sqlite query", sql);
-                    eprintln!("{}", msg);
-                }
-                RSQE::SqliteFailure(_, msg) => {
-                    eprintln!(
-                        "ERROR: {}",
-                        msg.unwrap_or("something bad happended".to_string())
-                    );
-                }
-                _ => eprintln!("ERROR: {:?}", e),
-            },
+            self.report_timing(stopwatch.elapsed());
+            return Ok(());
+        }
+
+        if explain_kind == 0 && self.ctx.eqp != EqpMode::Off {
+            self.print_auto_explain(&conn, query);
+        }
+
+        if col_count == 0 {
+            stmt.execute(params_from_iter(params))?;
+            self.report_timing(stopwatch.elapsed());
+            self.report_stats(&conn, &stmt);
+            return Ok(());
+        }
+
+        if self.ctx.explain == ExplainMode::On {
+            let column_names = util::query_title_row(&mut stmt, col_count, TableMode::List)?;
+            let row_datas = util::query_data_rows(
+                &mut stmt,
+                params_from_iter(params),
+                col_count,
+                TableMode::List,
+                self.ctx.null_value_repr.as_ref(),
+            )?;
+            let timing = stopwatch.elapsed();
+
+            self.write_trace();
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            explain::print_columns(writer, &column_names, &row_datas);
+            self.report_timing(timing);
+            self.report_stats(&conn, &stmt);
+            return Ok(());
         }
 
+        let column_names = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
+        let row_datas = if self.ctx.columnar.quote && self.ctx.mode.is_columnar() {
+            util::query_literal_rows(&mut stmt, params_from_iter(params), col_count)?
+        } else {
+            util::query_data_rows(
+                &mut stmt,
+                params_from_iter(params),
+                col_count,
+                self.ctx.mode,
+                self.ctx.null_value_repr.as_ref(),
+            )?
+        };
+        let timing = stopwatch.elapsed();
+
+        // the trace of the statement and its rows come before the rows themselves
+        self.write_trace();
+        util::construct_and_print_output(
+            &mut self.ctx.output,
+            self.ctx.mode,
+            column_names,
+            row_datas,
+            self.ctx.with_header,
+            &self.ctx.columnar,
+            &self.ctx.separators,
+        );
+        self.report_timing(timing);
+        self.report_stats(&conn, &stmt);
+
         Ok(())
     }
 
-    fn dot_archive(&mut self, _args: &[&str]) {}
-    fn dot_auth(&mut self, _args: &[&str]) {}
-    fn dot_bail(&mut self, _args: &[&str]) {}
-    fn dot_cd(&mut self, args: &[&str]) {
-        if args.is_empty() {
-            println!(".cd needs an argument");
+    fn print_auto_explain(&mut self, conn: &Connection, query: &str) {
+        let with_triggers = self.ctx.eqp == EqpMode::Trigger;
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+
+        let mut explain_with = |prefix: &str| -> rusqlite::Result<()> {
+            let mut stmt = conn.prepare(&format!("{} {}", prefix, query))?;
+            let params = util::lookup_parameters(conn, &stmt)?;
+            if prefix == "EXPLAIN" {
+                explain::print_bytecode(writer, &mut stmt, params_from_iter(params))
+            } else {
+                explain::print_query_plan(writer, &mut stmt, params_from_iter(params))
+            }
+        };
+
+        // plans of triggers are only reported while `SQLITE_DBCONFIG_TRIGGER_EQP` is on, the
+        // setting of the connection is put back afterwards
+        let saved_trigger_eqp = if with_triggers {
+            conn.db_config(DbConfig::SQLITE_DBCONFIG_TRIGGER_EQP).ok()
+        } else {
+            None
+        };
+        if saved_trigger_eqp.is_some() {
+            let _ = conn.set_db_config(DbConfig::SQLITE_DBCONFIG_TRIGGER_EQP, true);
+        }
+        let mut result = explain_with("EXPLAIN QUERY PLAN");
+        if let Some(saved) = saved_trigger_eqp {
+            let _ = conn.set_db_config(DbConfig::SQLITE_DBCONFIG_TRIGGER_EQP, saved);
+        }
+        if result.is_ok() && self.ctx.eqp == EqpMode::Full {
+            result = explain_with("EXPLAIN");
+        }
+
+        if let Err(e) = result {
+            eprintln!("ERROR: unable to explain the query: {}", e);
+        }
+    }
+
+    fn write_trace(&mut self) {
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        trace::write_pending(writer);
+    }
+
+    fn report_timing(&mut self, timing: Timing) {
+        self.total_time.add(timing);
+        if !self.ctx.with_timer {
+            return;
+        }
+
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        let _ = writeln!(writer, "Run Time: {}", timing);
+        writer.flush().expect("unable to flush");
+    }
+
+    fn report_lock_wait(&mut self) {
+        let waited = busy::waited();
+        if !self.ctx.timeout.verbose || waited.is_zero() {
             return;
         }
 
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        let _ = writeln!(writer, "Lock wait: {} ms", waited.as_millis());
+        writer.flush().expect("unable to flush");
+    }
+
+    // `stmt` must not be finalized yet
+    fn report_stats(&mut self, conn: &Connection, stmt: &Statement) {
+        let mode = self.ctx.stats;
+        if mode == StatsMode::Off {
+            return;
+        }
+
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        match mode {
+            StatsMode::On => {
+                stats::print_connection_stats(writer, conn);
+                stats::print_statement_stats(writer, stmt);
+            }
+            StatsMode::Stmt => stats::print_statement_stats(writer, stmt),
+            StatsMode::VmStep => stats::print_vm_steps(writer, stmt),
+            StatsMode::Off => {}
+        }
+        writer.flush().expect("unable to flush");
+    }
+
+    fn dot_archive(&mut self, args: &[&str]) -> DotResult {
+        let opts = match ArchiveOptions::parse(args) {
+            Ok(opts) => opts,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+
+        // cast this into a trait object to reduce duplicate code
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+
+        let conn = self.ctx.conn.borrow();
+        archive::run_archive(&conn, &opts, &self.ctx.cwd, writer)
+            .map_err(|e| DotError::Failed(format!("{:#}", e)))
+    }
+    fn dot_auth(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_bail(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(".bail needs an argument".to_string()));
+        }
+
+        self.ctx.bail = util::on_or_off_to_bool(args[0]);
+        Ok(())
+    }
+    fn dot_cd(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(".cd needs an argument".to_string()));
+        }
+
         let path = PathBuf::from(args[0]);
         if !path.exists() {
-            println!("path doesn't exist {}", path.display());
-            return;
+            return Err(DotError::Failed(format!(
+                "path doesn't exist {}",
+                path.display()
+            )));
         }
 
         if path.is_absolute() {
             self.ctx.cwd = path;
-            return;
         } else if path.is_dir() && path.is_relative() {
             self.ctx.cwd.push(path);
         }
+        Ok(())
+    }
+    fn dot_changes(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_check(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_clone(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_connection(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_crlf(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
     }
-    fn dot_changes(&mut self, _args: &[&str]) {}
-    fn dot_check(&mut self, _args: &[&str]) {}
-    fn dot_clone(&mut self, _args: &[&str]) {}
-    fn dot_connection(&mut self, _args: &[&str]) {}
-    fn dot_crlf(&mut self, _args: &[&str]) {}
-    fn dot_databases(&mut self, _args: &[&str]) -> rusqlite::Result<()> {
+    fn dot_databases(&mut self, _args: &[&str]) -> DotResult {
         let sql = "SELECT seq , name , file FROM pragma_database_list";
         let conn = self.ctx.conn.borrow();
         let mut stmt = conn.prepare(sql)?;
@@ -195,6 +535,7 @@ impl<'a> CommandRunner<'a> {
         let title = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
         let data = util::query_data_rows(
             &mut stmt,
+            (),
             col_count,
             self.ctx.mode,
             self.ctx.null_value_repr.as_ref(),
@@ -206,150 +547,132 @@ impl<'a> CommandRunner<'a> {
             title,
             data,
             self.ctx.with_header,
+            &self.ctx.columnar,
+            &self.ctx.separators,
         );
 
         Ok(())
     }
-    fn dot_dbconfig(&mut self, _args: &[&str]) {
+    fn dot_dbconfig(&mut self, _args: &[&str]) -> DotResult {
         let attach_create = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_ATTACH_CREATE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_ATTACH_CREATE)?,
         );
         let attach_write = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_ATTACH_WRITE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_ATTACH_WRITE)?,
         );
         let comments = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_COMMENTS)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_COMMENTS)?,
         );
         let defensive = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_DEFENSIVE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_DEFENSIVE)?,
         );
         let dps_ddl = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_DQS_DDL)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_DQS_DDL)?,
         );
         let dps_dml = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_DQS_DML)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_DQS_DML)?,
         );
         let enable_fkey = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FKEY)?,
         );
         let enable_qpsg = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_QPSG)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_QPSG)?,
         );
         let enable_trigger = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_TRIGGER)?,
         );
         let enable_view = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_VIEW)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_VIEW)?,
         );
         let fts3_tokenizer = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FTS3_TOKENIZER)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_ENABLE_FTS3_TOKENIZER)?,
         );
         let legacy_alter_table = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_LEGACY_ALTER_TABLE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_LEGACY_ALTER_TABLE)?,
         );
         let legacy_file_format = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_LEGACY_FILE_FORMAT)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_LEGACY_FILE_FORMAT)?,
         );
         let no_ckpt_on_close = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_NO_CKPT_ON_CLOSE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_NO_CKPT_ON_CLOSE)?,
         );
         let reset_database = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE)?,
         );
         let reverse_scanorder = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_REVERSE_SCANORDER)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_REVERSE_SCANORDER)?,
         );
         let stmt_scanstatus = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_STMT_SCANSTATUS)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_STMT_SCANSTATUS)?,
         );
         let trigger_eqp = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_TRIGGER_EQP)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_TRIGGER_EQP)?,
         );
         let trusted_schema = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_TRUSTED_SCHEMA)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_TRUSTED_SCHEMA)?,
         );
         let writable_schema = util::bool_to_on_or_off(
             self.ctx
                 .conn
                 .borrow()
-                .db_config(DbConfig::SQLITE_DBCONFIG_WRITABLE_SCHEMA)
-                .unwrap(),
+                .db_config(DbConfig::SQLITE_DBCONFIG_WRITABLE_SCHEMA)?,
         );
         let mut tbl = table! {
             ["attach_create", attach_create],
@@ -380,99 +703,107 @@ impl<'a> CommandRunner<'a> {
         tbl.set_format(fmt);
 
         self.ctx.output.print_prettytable(&mut tbl);
+        Ok(())
+    }
+    fn dot_dbinfo(&mut self, args: &[&str]) -> DotResult {
+        let db_name = args.first().copied().unwrap_or("main");
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+
+        let result = dbinfo::print_db_info(&self.ctx.conn.borrow(), db_name, writer);
+        let _ = writer.flush();
+        result.map_err(|e| DotError::Failed(e.to_string()))
+    }
+    fn dot_dbtotxt(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
     }
-    fn dot_dbinfo(&mut self, _args: &[&str]) {}
-    fn dot_dbtotxt(&mut self, _args: &[&str]) {}
-    fn dot_dump(&mut self, _args: &[&str]) {
+    fn dot_dump(&mut self, args: &[&str]) -> DotResult {
+        let opts = match DumpOptions::parse(args) {
+            Ok(opts) => opts,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+
         // cast this into a trait object to reduce duplicate code
         let writer: &mut dyn Write = match &mut self.ctx.output {
             Output::BufferedStdout(out) => out,
-            Output::BufferedFile(f) => f,
+            Output::BufferedFile(f, _) => f,
         };
 
-        // disable foreign keys constraint and create a transaction, this is important because
-        // if the dumping process goes wrong, the results would be invalid. Therefore, we must put all of this operation
-        // in a transaction block
-        let _ = writeln!(writer, "PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;");
+        dump::dump_database(&self.ctx.conn.borrow(), writer, &opts)
+            .map_err(|e| DotError::Failed(e.to_string()))
+    }
+    fn dot_echo(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(".echo needs an argument".to_string()));
+        }
 
-        // get all things that the users creates
-        let sql = "SELECT name, type, sql FROM sqlite_schema WHERE name NOT LIKE '%_autoindex_%'";
-        let conn = self.ctx.conn.borrow();
-        let mut items_stmt = conn
-            .prepare(sql)
-            .expect("unable to prepare query for dumping");
-        let col_count = items_stmt.column_count();
+        let confirmation = util::on_or_off_to_bool(args[0]);
+        self.ctx.with_echo = confirmation;
+        Ok(())
+    }
+    fn dot_exit(&mut self, args: &[&str]) -> DotResult {
+        let exit_code = args.first().and_then(|code| code.parse().ok()).unwrap_or(0);
+        self.ctx.output.flush();
+        exit(exit_code);
+    }
+    fn dot_eqp(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(".eqp needs an argument".to_string()));
+        }
 
-        let created_items = util::query_data_rows(
-            &mut items_stmt,
-            col_count,
-            TableMode::Box,
-            self.ctx.null_value_repr.as_ref(),
-        )
-        .expect("unable to query sqlite_schema");
-
-        // dump the sql representation of all things that the users creates
-        for items in created_items {
-            let item_name = &items[0];
-            let item_type = &items[1];
-            let item_sql = &items[2];
-            // if the user creates a table or a view, we must populate the table with INSERT INTO statements if there are items inside it.
-            if item_type == "table" || item_type == "view" {
-                let table_to_populate_sql = format!("SELECT * FROM {item_name}");
-                let conn = self.ctx.conn.borrow();
-                let mut insert_into_stmt = conn
-                    .prepare(&table_to_populate_sql)
-                    .expect("unable to prepare query for populating data");
-
-                let col_count = insert_into_stmt.column_count();
-
-                let insert_datas = util::query_data_rows(
-                    &mut insert_into_stmt,
-                    col_count,
-                    TableMode::Quote,
-                    self.ctx.null_value_repr.as_ref(),
-                )
-                .expect("unable to get table content");
-
-                for values in insert_datas {
-                    let values = values.join(",");
-                    let _ = writeln!(writer, "INSERT INTO {} VALUES ({});", item_name, values);
-                }
-            } else {
-                // if its not a table, maybe an index or a trigger we could just print the sql.
-                let _ = writeln!(writer, "{};", item_sql);
+        match EqpMode::try_from(args[0]) {
+            Ok(eqp) => self.ctx.eqp = eqp,
+            Err(_) => {
+                return Err(DotError::Usage(
+                    "unexpected input, input must be one of on, off, full or trigger".to_string(),
+                ));
             }
         }
-
-        // commit those changes
-        let _ = writeln!(writer, "COMMIT;");
-        // don't forget to flush!
-        writer.flush().expect("unable to flush");
+        Ok(())
+    }
+    fn dot_excel(&mut self, _args: &[&str]) -> DotResult {
+        self.dot_once(&["-x"])
+    }
+    fn dot_expert(&mut self, _args: &[&str]) -> DotResult {
+        self.ctx.expert = true;
+        println!("the next statement is analyzed instead of executed");
+        Ok(())
     }
-    fn dot_echo(&mut self, args: &[&str]) {
+    fn dot_explain(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".echo needs an argument");
-            return;
+            self.ctx.explain = ExplainMode::On;
+            return Ok(());
         }
 
-        let confirmation = util::on_or_off_to_bool(args[0]);
-        self.ctx.with_echo = confirmation;
+        match ExplainMode::try_from(args[0]) {
+            Ok(explain) => self.ctx.explain = explain,
+            Err(_) => {
+                return Err(DotError::Usage(
+                    "unexpected input, input must be one of on, off or auto".to_string(),
+                ));
+            }
+        }
+        Ok(())
     }
-    fn dot_eqp(&mut self, _args: &[&str]) {}
-    fn dot_excel(&mut self, _args: &[&str]) {}
-    fn dot_expert(&mut self, _args: &[&str]) {}
-    fn dot_explain(&mut self, _args: &[&str]) {}
-    fn dot_filectrl(&mut self, _args: &[&str]) {}
-    fn dot_fullschema(&mut self, _args: &[&str]) {}
-    fn dot_headers(&mut self, args: &[&str]) {
+    fn dot_filectrl(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_fullschema(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_headers(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".headers needs at least an argument");
-            return;
+            return Err(DotError::Usage(
+                ".headers needs at least an argument".to_string(),
+            ));
         }
 
         self.ctx.with_header = util::on_or_off_to_bool(args[0]);
+        Ok(())
     }
-    fn dot_help(&mut self, _args: &[&str]) {
+    fn dot_help(&mut self, _args: &[&str]) -> DotResult {
         let mut table = Table::new();
         // format titles with a center alignment based on `Prettytable::Cell::style_spec`
         table.set_titles(row![c => "command", "args", "desc"]);
@@ -483,10 +814,42 @@ impl<'a> CommandRunner<'a> {
         let fmt = TableFormat::try_from(self.ctx.mode).unwrap_or(*crate::consts::BOX);
         table.set_format(fmt);
         self.ctx.output.print_prettytable(&mut table);
+        Ok(())
+    }
+    fn dot_import(&mut self, args: &[&str]) -> DotResult {
+        // without an explicit format option, follow the current output mode
+        let default_sep = match self.ctx.mode {
+            TableMode::Tabs => b'\t',
+            _ => b',',
+        };
+
+        let opts = match ImportOptions::parse(args, default_sep) {
+            Ok(opts) => opts,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+
+        let path = self.ctx.cwd.join(&opts.file);
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        let result = import::import_file(&self.ctx.conn.borrow(), &path, &opts, writer);
+        let _ = writer.flush();
+
+        let summary = result.map_err(|e| DotError::Failed(format!("{:#}", e)))?;
+        if summary.created {
+            println!("created table {}", opts.table);
+        }
+        println!(
+            "{} rows inserted into {}, {} rows rejected",
+            summary.inserted, opts.table, summary.rejected
+        );
+        Ok(())
+    }
+    fn dot_imposter(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
     }
-    fn dot_import(&mut self, _args: &[&str]) {}
-    fn dot_imposter(&mut self, _args: &[&str]) {}
-    fn dot_indexes(&mut self, _args: &[&str]) -> rusqlite::Result<()> {
+    fn dot_indexes(&mut self, _args: &[&str]) -> DotResult {
         let sql =
             "SELECT name FROM sqlite_schema WHERE type = 'index' AND name NOT LIKE 'sqlite_%'";
         let conn = self.ctx.conn.borrow();
@@ -496,6 +859,7 @@ impl<'a> CommandRunner<'a> {
         let title = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
         let table_names = util::query_data_rows(
             &mut stmt,
+            (),
             col_count,
             self.ctx.mode,
             self.ctx.null_value_repr.as_ref(),
@@ -507,139 +871,355 @@ impl<'a> CommandRunner<'a> {
             title,
             table_names,
             true,
+            &self.ctx.columnar,
+            &self.ctx.separators,
         );
 
         Ok(())
     }
-    fn dot_intck(&mut self, _args: &[&str]) {}
-    fn dot_limit(&mut self, _args: &[&str]) {}
-    fn dot_lint(&mut self, _args: &[&str]) {}
-    fn dot_load(&mut self, _args: &[&str]) {}
-    fn dot_log(&mut self, _args: &[&str]) {}
-    fn dot_mode(&mut self, args: &[&str]) {
+    fn dot_intck(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_limit(&mut self, args: &[&str]) -> DotResult {
+        if args.len() > 2 {
+            return Err(DotError::Usage(
+                "Usage: .limit NAME ?NEW-VALUE?".to_string(),
+            ));
+        }
+
+        // a name may be shortened as long as only one limit starts with it
+        let limits: Vec<(&str, Limit)> = match args.first() {
+            None => SQLITE_LIMITS.to_vec(),
+            Some(name) => {
+                let name = name.to_ascii_lowercase();
+                let matches: Vec<(&str, Limit)> = SQLITE_LIMITS
+                    .iter()
+                    .filter(|(limit_name, _)| limit_name.starts_with(&name))
+                    .copied()
+                    .collect();
+                match &matches[..] {
+                    [] => {
+                        return Err(DotError::Usage(format!(
+                            "unknown limit: \"{}\"\nenter \".limit\" with no arguments for a list.",
+                            name
+                        )));
+                    }
+                    [_] => matches,
+                    _ => match matches.iter().find(|(limit_name, _)| *limit_name == name) {
+                        Some(&exact) => vec![exact],
+                        None => {
+                            return Err(DotError::Usage(format!("ambiguous limit: \"{}\"", name)));
+                        }
+                    },
+                }
+            }
+        };
+
+        let conn = self.ctx.conn.borrow();
+        if let Some(value) = args.get(1) {
+            let new_value = match value.parse::<i32>() {
+                Ok(new_value) => new_value,
+                Err(_) => return Err(DotError::Usage(format!("invalid limit: \"{}\"", value))),
+            };
+            conn.set_limit(limits[0].1, new_value)?;
+        }
+
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        for (name, limit) in limits {
+            let _ = writeln!(writer, "{:>20} {}", name, conn.limit(limit)?);
+        }
+        let _ = writer.flush();
+        Ok(())
+    }
+    fn dot_lint(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_load(&mut self, args: &[&str]) -> DotResult {
+        let (file, entry) = match args
+            .iter()
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>()[..]
+        {
+            [file] => (*file, None),
+            [file, entry] => (*file, Some(*entry)),
+            _ => {
+                return Err(DotError::Usage("Usage: .load FILE ?ENTRY?".to_string()));
+            }
+        };
+
+        self.ctx
+            .load_extension(file, entry)
+            .map_err(DotError::Failed)
+    }
+    fn dot_log(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_mode(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".mode needs at least an argument");
-            return;
+            return Err(DotError::Usage(
+                ".mode needs at least an argument".to_string(),
+            ));
         }
 
-        self.ctx.mode = TableMode::try_from(args[0]).expect("unrecognized command");
+        let mode = match TableMode::try_from(args[0]) {
+            Ok(mode) => mode,
+            Err(_) => return Err(DotError::Usage(format!("unrecognized mode: {}", args[0]))),
+        };
+        match self.ctx.columnar.parse(&args[1..]) {
+            Ok(columnar) => {
+                self.ctx.switch_mode(mode);
+                self.ctx.columnar = columnar;
+            }
+            Err(msg) => return Err(DotError::Usage(msg)),
+        }
+        Ok(())
     }
-    fn dot_nonce(&mut self, _args: &[&str]) {}
-    fn dot_nullvalue(&mut self, args: &[&str]) {
+    fn dot_nonce(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_nullvalue(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".nullvalue needs an argument");
-            return;
+            return Err(DotError::Usage(".nullvalue needs an argument".to_string()));
         }
 
         self.ctx.null_value_repr = Some(args[0].to_string());
+        Ok(())
+    }
+    fn dot_once(&mut self, args: &[&str]) -> DotResult {
+        let target = match OnceTarget::parse(args) {
+            Ok(target) => target,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+        let (output, path) = match once::create_output(&target, &self.ctx.cwd) {
+            Ok(created) => created,
+            Err(msg) => return Err(DotError::Failed(msg)),
+        };
+
+        // a second `.once` replaces the first one, but the original output comes back
+        let previous = self.ctx.once.take();
+        let previous_output = std::mem::replace(&mut self.ctx.output, output);
+        let mut redirect = previous.unwrap_or(OnceRedirect {
+            previous_output,
+            previous_mode: self.ctx.mode,
+            previous_header: self.ctx.with_header,
+            open_with: None,
+        });
+
+        redirect.open_with = match target {
+            OnceTarget::File(_) => None,
+            OnceTarget::Editor => Some((once::editor(), path)),
+            OnceTarget::Spreadsheet => {
+                // spreadsheets get csv with the column names in the first row
+                self.ctx.mode = TableMode::Csv;
+                self.ctx.with_header = true;
+                Some((self.ctx.launcher.clone(), path))
+            }
+        };
+        self.ctx.once = Some(redirect);
+        Ok(())
     }
-    fn dot_once(&mut self, _args: &[&str]) {}
-    fn dot_open(&mut self, args: &[&str]) {
+    fn dot_open(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".open needs an argument");
-            return;
+            return Err(DotError::Usage(".open needs an argument".to_string()));
         }
 
-        let path = Path::new(args[0]);
-        self.ctx.cwd.push(path);
-
-        let new_conn =
-            Connection::open(&self.ctx.cwd).expect("unable to establish a new database connection");
+        let new_conn = Connection::open(self.ctx.db_path(args[0]))?;
 
+        // sessions belong to the connection that is about to be closed
+        self.ctx.sessions.clear();
         *self.ctx.conn.borrow_mut() = new_conn;
+        trace::install(&self.ctx.conn.borrow(), self.ctx.trace.as_ref());
+        interrupt::watch(&self.ctx.conn.borrow());
+        busy::install(&self.ctx.conn.borrow(), self.ctx.timeout);
+        Ok(())
+    }
+    fn dot_output(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(
+                ".output needs at least on argument".to_string(),
+            ));
+        }
 
-        self.ctx.cwd.pop();
+        let path = self.ctx.cwd.join(args[0]);
+        let f = File::create(&path)
+            .map_err(|e| DotError::Failed(format!("cannot open \"{}\": {}", args[0], e)))?;
+        let bufwriter = BufWriter::new(f);
+        self.ctx.output = Output::BufferedFile(bufwriter, path);
+        Ok(())
     }
-    fn dot_output(&mut self, args: &[&str]) {
+    fn dot_parameter(&mut self, args: &[&str]) -> DotResult {
+        let args: Vec<&str> = args.iter().copied().filter(|arg| !arg.is_empty()).collect();
         if args.is_empty() {
-            println!(".output needs at least on argument");
-            return;
+            return Err(DotError::Usage(
+                ".parameter needs at least an argument: init, list, set, unset or clear"
+                    .to_string(),
+            ));
         }
 
-        let path = Path::new(args[0]);
-        self.ctx.cwd.push(path);
+        if args[..] == ["list"] {
+            return Ok(self.list_parameters()?);
+        }
 
-        if self.ctx.cwd.exists() {
-            let f = File::open(&self.ctx.cwd).unwrap();
-            let bufwriter = BufWriter::new(f);
-            self.ctx.output = Output::BufferedFile(bufwriter);
-            return;
+        let conn = self.ctx.conn.borrow();
+
+        let result = match (args[0], &args[1..]) {
+            ("init", []) => util::init_parameter_table(&conn),
+            ("clear", []) => conn
+                .execute("DROP TABLE IF EXISTS temp.sqlite_parameters", ())
+                .map(|_| ()),
+            ("unset", [name]) => match util::parameter_table_exists(&conn) {
+                Ok(true) => conn
+                    .execute("DELETE FROM temp.sqlite_parameters WHERE key = ?1", [name])
+                    .map(|_| ()),
+                other => other.map(|_| ()),
+            },
+            ("set", [name, value @ ..]) if !value.is_empty() => {
+                let value = value.join(" ");
+                util::init_parameter_table(&conn).and_then(|_| {
+                    // the value is an sql expression, if it isn't valid it is stored as text
+                    let as_expr = format!(
+                        "REPLACE INTO temp.sqlite_parameters(key, value) VALUES (?1, {})",
+                        value
+                    );
+                    conn.execute(&as_expr, [name])
+                        .or_else(|_| {
+                            conn.execute(
+                                "REPLACE INTO temp.sqlite_parameters(key, value) VALUES (?1, ?2)",
+                                [name, &value.as_str()],
+                            )
+                        })
+                        .map(|_| ())
+                })
+            }
+            _ => {
+                return Err(DotError::Usage(
+                    "usage: .parameter init | list | clear | set NAME VALUE | unset NAME"
+                        .to_string(),
+                ));
+            }
+        };
+
+        Ok(result?)
+    }
+    fn list_parameters(&mut self) -> rusqlite::Result<()> {
+        let conn = self.ctx.conn.borrow();
+        if !util::parameter_table_exists(&conn)? {
+            return Ok(());
         }
 
-        let f = File::create(&self.ctx.cwd).expect("unable to create file");
-        let bufwriter = BufWriter::new(f);
-        self.ctx.output = Output::BufferedFile(bufwriter);
+        let sql = "SELECT key, quote(value) AS value FROM temp.sqlite_parameters ORDER BY key";
+        let mut stmt = conn.prepare(sql)?;
+        let col_count = stmt.column_count();
+
+        let title = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
+        let data = util::query_data_rows(
+            &mut stmt,
+            (),
+            col_count,
+            self.ctx.mode,
+            self.ctx.null_value_repr.as_ref(),
+        )?;
 
-        self.ctx.cwd.pop();
+        util::construct_and_print_output(
+            &mut self.ctx.output,
+            self.ctx.mode,
+            title,
+            data,
+            true,
+            &self.ctx.columnar,
+            &self.ctx.separators,
+        );
+
+        Ok(())
     }
-    fn dot_parameter(&mut self, _args: &[&str]) {}
-    fn dot_print(&mut self, args: &[&str]) {
+    fn dot_print(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
             println!();
-            return;
+            return Ok(());
         }
 
         println!("{}", args.join(" "));
+        Ok(())
+    }
+    fn dot_progress(&mut self, args: &[&str]) -> DotResult {
+        match ProgressOptions::parse(args) {
+            Ok(opts) => {
+                interrupt::set_progress(&self.ctx.conn.borrow(), opts);
+                self.ctx.progress = opts;
+            }
+            Err(msg) => return Err(DotError::Usage(msg)),
+        }
+        Ok(())
+    }
+    fn dot_prompt(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
     }
-    fn dot_progress(&mut self, _args: &[&str]) {}
-    fn dot_prompt(&mut self, _args: &[&str]) {}
-    fn dot_read(&mut self, args: &[&str]) {
+    fn dot_read(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".read needs an argument");
-            return;
+            return Err(DotError::Usage(".read needs an argument".to_string()));
         }
 
-        let path = Path::new(args[0]);
-        self.ctx.cwd.push(path);
-
-        let db_file = File::open(&self.ctx.cwd).expect("unable to open file");
-        let reader = BufReader::new(db_file);
-        reader.split(b';').flatten().for_each(|sql| {
-            let sql_str = str::from_utf8(&sql).expect("encountered a non-utf8 character");
-            let trim = sql_str.trim();
-            if !trim.is_empty() {
-                self.run_user_query(trim).expect("unable to run user query");
+        let script = match File::open(self.ctx.cwd.join(args[0])) {
+            Ok(script) => script,
+            Err(e) => {
+                return Err(DotError::Failed(format!(
+                    "cannot open \"{}\": {}",
+                    args[0], e
+                )));
             }
-        });
+        };
+
+        self.run_script(BufReader::new(script), args[0]);
+        // the errors of the script are already counted by the statements and commands in it
+        Ok(())
+    }
+    fn dot_recover(&mut self, args: &[&str]) -> DotResult {
+        let opts = match RecoverOptions::parse(args) {
+            Ok(opts) => opts,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+
+        // cast this into a trait object to reduce duplicate code
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
 
-        self.ctx.cwd.pop();
+        recover::recover_database(&self.ctx.conn.borrow(), writer, &opts)
+            .map_err(|e| DotError::Failed(e.to_string()))
     }
-    fn dot_recover(&mut self, _args: &[&str]) {}
-    fn dot_restore(&mut self, args: &[&str]) {
+    fn dot_restore(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".restore needs an argument");
-            return;
+            return Err(DotError::Usage(".restore needs an argument".to_string()));
         }
 
-        let path = Path::new(args[0]);
-        self.ctx.cwd.push(path);
-
+        let path = self.ctx.cwd.join(args[0]);
         self.ctx
             .conn
             .borrow_mut()
-            .restore(MAIN_DB, &self.ctx.cwd, Some(util::show_progress))
-            .expect("unable to backup");
-
-        self.ctx.cwd.pop();
+            .restore(MAIN_DB, path, Some(util::show_progress))?;
+        Ok(())
     }
-    fn dot_save(&mut self, args: &[&str]) {
+    fn dot_save(&mut self, args: &[&str]) -> DotResult {
         if args.is_empty() {
-            println!(".save or .backup needs an argument");
-            return;
+            return Err(DotError::Usage(
+                ".save or .backup needs an argument".to_string(),
+            ));
         }
-        let path = Path::new(args[0]);
-        self.ctx.cwd.push(path);
-
+        let path = self.ctx.cwd.join(args[0]);
         self.ctx
             .conn
             .borrow()
-            .backup(MAIN_DB, &self.ctx.cwd, Some(util::show_progress))
-            .unwrap();
-
-        self.ctx.cwd.pop();
+            .backup(MAIN_DB, path, Some(util::show_progress))?;
+        Ok(())
     }
-    fn dot_scanstats(&mut self, _args: &[&str]) {}
-    fn dot_schema(&mut self, args: &[&str]) -> rusqlite::Result<()> {
+    fn dot_scanstats(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_schema(&mut self, args: &[&str]) -> DotResult {
         let mut sql = "SELECT sql FROM sqlite_schema WHERE name NOT LIKE '%_autoindex_%' ORDER BY tbl_name, type DESC, name".to_string();
 
         if !args.is_empty() {
@@ -652,6 +1232,7 @@ impl<'a> CommandRunner<'a> {
 
         let table_names = util::query_data_rows(
             &mut stmt,
+            (),
             1,
             TableMode::Box,
             self.ctx.null_value_repr.as_ref(),
@@ -659,7 +1240,7 @@ impl<'a> CommandRunner<'a> {
 
         let writer: &mut dyn Write = match &mut self.ctx.output {
             Output::BufferedStdout(out) => out,
-            Output::BufferedFile(f) => f,
+            Output::BufferedFile(f, _) => f,
         };
 
         for table in table_names {
@@ -672,15 +1253,342 @@ impl<'a> CommandRunner<'a> {
 
         Ok(())
     }
-    fn dot_separator(&mut self, _args: &[&str]) {}
-    fn dot_session(&mut self, _args: &[&str]) {}
-    fn dot_sha3sum(&mut self, _args: &[&str]) {}
-    fn dot_show(&mut self, _args: &[&str]) {}
-    fn dot_stats(&mut self, _args: &[&str]) {}
-    fn dot_system(&mut self, args: &[&str]) {
+    fn dot_separator(&mut self, args: &[&str]) -> DotResult {
+        let args: Vec<&str> = args
+            .iter()
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| util::strip_quotes(arg))
+            .collect();
+        match args[..] {
+            [column] => {
+                self.ctx.separators.column = util::unescape(column).map_err(DotError::Failed)?
+            }
+            [column, row] => {
+                self.ctx.separators.column = util::unescape(column).map_err(DotError::Failed)?;
+                self.ctx.separators.row = util::unescape(row).map_err(DotError::Failed)?;
+            }
+            _ => return Err(DotError::Usage("Usage: .separator COL ?ROW?".to_string())),
+        }
+        Ok(())
+    }
+    fn dot_session(&mut self, args: &[&str]) -> DotResult {
+        let args: Vec<&str> = args.iter().copied().filter(|arg| !arg.is_empty()).collect();
+        let usage = "Usage: .session ?NAME? CMD ...\n\
+                     \x20   attach TABLE       attach TABLE, or every table with *\n\
+                     \x20   changeset FILE     write a changeset into FILE\n\
+                     \x20   close              close the session\n\
+                     \x20   enable ?BOOLEAN?   set or query the enable flag\n\
+                     \x20   filter GLOB...     ignore the tables that match any GLOB\n\
+                     \x20   indirect ?BOOLEAN? set or query the indirect flag\n\
+                     \x20   isempty            query whether the session recorded changes\n\
+                     \x20   list               list the open sessions\n\
+                     \x20   open DB NAME       open a session named NAME on DB\n\
+                     \x20   patchset FILE      write a patchset into FILE\n\
+                     .session apply FILE ?--omit|--replace|--abort?\n\
+                     \x20   apply a changeset or a patchset to the main database";
+
+        match args[..] {
+            [] => Err(DotError::Usage(usage.to_string())),
+            ["list", ..] => {
+                for (idx, session) in self.ctx.sessions.iter().enumerate() {
+                    println!("{} {}", idx, session.name);
+                }
+                Ok(())
+            }
+            ["open", db, name] => {
+                if self.ctx.sessions.iter().any(|session| session.name == name) {
+                    return Err(DotError::Failed(format!(
+                        "session \"{}\" already exists",
+                        name
+                    )));
+                }
+                let session =
+                    Session::open(&self.ctx.conn.borrow(), db, name).map_err(DotError::Failed)?;
+                self.ctx.sessions.push(session);
+                Ok(())
+            }
+            ["apply", file, ref options @ ..] => {
+                let policy = match options {
+                    [] | ["--omit"] => ConflictPolicy::Omit,
+                    ["--replace"] => ConflictPolicy::Replace,
+                    ["--abort"] => ConflictPolicy::Abort,
+                    _ => return Err(DotError::Usage(usage.to_string())),
+                };
+                let changeset = match std::fs::read(self.ctx.cwd.join(file)) {
+                    Ok(changeset) => changeset,
+                    Err(e) => {
+                        return Err(DotError::Failed(format!("cannot read \"{}\": {}", file, e)));
+                    }
+                };
+                let conflicts =
+                    session::apply_changeset(&self.ctx.conn.borrow(), &changeset, policy)
+                        .map_err(DotError::Failed)?;
+                // the policy decides what happens to the conflicting changes, the command still
+                // fails so that .bail and the exit status see them
+                if conflicts > 0 {
+                    return Err(DotError::Failed(format!(
+                        "{} conflict(s) while applying \"{}\"",
+                        conflicts, file
+                    )));
+                }
+                Ok(())
+            }
+            _ => {
+                // the session is named by the first argument, or it is the first open session
+                let named = self
+                    .ctx
+                    .sessions
+                    .iter()
+                    .position(|session| args.len() > 1 && session.name == args[0]);
+                let (idx, args) = match named {
+                    Some(idx) => (idx, &args[1..]),
+                    None => (0, &args[..]),
+                };
+                if self.ctx.sessions.is_empty() {
+                    return Err(DotError::Failed("no session is open".to_string()));
+                }
+
+                self.run_session_command(idx, args, usage)
+            }
+        }
+    }
+
+    fn run_session_command(&mut self, idx: usize, args: &[&str], usage: &str) -> DotResult {
+        let session = &mut self.ctx.sessions[idx];
+
+        match args {
+            ["attach", table] => {
+                let table = (*table != "*").then_some(*table);
+                session.attach(table).map_err(DotError::Failed)?;
+            }
+            [kind @ ("changeset" | "patchset"), file] => {
+                let changes = match session.changes(*kind == "patchset") {
+                    Ok(changes) => changes,
+                    Err(msg) => return Err(DotError::Failed(msg)),
+                };
+                std::fs::write(self.ctx.cwd.join(file), changes)
+                    .map_err(|e| DotError::Failed(format!("cannot write \"{}\": {}", file, e)))?;
+            }
+            ["close"] => {
+                self.ctx.sessions.remove(idx);
+            }
+            ["enable", flag @ ..] | ["indirect", flag @ ..] if flag.len() <= 1 => {
+                let flag = flag.first().map(|flag| util::on_or_off_to_bool(flag));
+                let value = if args[0] == "enable" {
+                    session.enable(flag)
+                } else {
+                    session.indirect(flag)
+                };
+                println!(
+                    "session {} {} flag = {}",
+                    session.name, args[0], value as i32
+                );
+            }
+            ["filter", globs @ ..] => {
+                session.set_filter(globs).map_err(DotError::Failed)?;
+            }
+            ["isempty"] => {
+                println!(
+                    "session {} isempty flag = {}",
+                    session.name,
+                    session.is_empty() as i32
+                );
+            }
+            _ => return Err(DotError::Usage(usage.to_string())),
+        }
+        Ok(())
+    }
+    fn dot_sha3sum(&mut self, args: &[&str]) -> DotResult {
+        let opts = match Sha3sumOptions::parse(args) {
+            Ok(opts) => opts,
+            Err(msg) => return Err(DotError::Usage(msg)),
+        };
+
+        let conn = self.ctx.conn.borrow();
+        if opts.debug {
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            for query in sha3sum::debug_queries(&conn, &opts)? {
+                let _ = writeln!(writer, "{}", query);
+            }
+            writer.flush().expect("unable to flush");
+            return Ok(());
+        }
+
+        let hashes = sha3sum::hash_database(&conn, &opts)?;
+        drop(conn);
+
+        let (title, data) = if opts.pattern.is_some() {
+            let data = hashes
+                .into_iter()
+                .map(|(hash, label)| vec![hash, label.unwrap_or_default()])
+                .collect();
+            (vec!["hash".to_string(), "label".to_string()], data)
+        } else {
+            let data = hashes.into_iter().map(|(hash, _)| vec![hash]).collect();
+            (vec!["hash".to_string()], data)
+        };
+
+        util::construct_and_print_output(
+            &mut self.ctx.output,
+            self.ctx.mode,
+            title,
+            data,
+            self.ctx.with_header,
+            &self.ctx.columnar,
+            &self.ctx.separators,
+        );
+        Ok(())
+    }
+    fn dot_show(&mut self, _args: &[&str]) -> DotResult {
+        // no `..` here, so a new setting can't be added without deciding how it is shown. The
+        // trace and the progress handler live outside of the context and are asked for below
+        let Context {
+            conn,
+            output,
+            mode,
+            command: _,
+            cwd,
+            with_header,
+            with_echo,
+            with_timer,
+            eqp,
+            explain,
+            expert,
+            null_value_repr,
+            sessions,
+            bail,
+            // the exit status and a pending `.once` are state rather than settings
+            error_count: _,
+            once: _,
+            launcher,
+            columnar,
+            separators,
+            stats,
+            timeout,
+            progress,
+            trace,
+        } = &*self.ctx;
+
+        let mode_name: &str = (*mode).into();
+        let mode = if mode.is_columnar() {
+            format!(
+                "{} --wrap {} --wordwrap {} --{}",
+                mode_name,
+                columnar.wrap,
+                util::bool_to_on_or_off(columnar.word_wrap),
+                if columnar.quote { "quote" } else { "noquote" }
+            )
+        } else {
+            mode_name.to_string()
+        };
+        let filename = match conn.borrow().path() {
+            Some(path) if !path.is_empty() => path.to_string(),
+            _ => ":memory:".to_string(),
+        };
+        let widths: Vec<String> = columnar.widths.iter().map(i32::to_string).collect();
+        let session_names: Vec<&str> = sessions
+            .iter()
+            .map(|session| session.name.as_str())
+            .collect();
+
+        let settings = [
+            ("bail", util::bool_to_on_or_off(*bail).to_string()),
+            ("echo", util::bool_to_on_or_off(*with_echo).to_string()),
+            ("eqp", <&str>::from(*eqp).to_string()),
+            ("explain", <&str>::from(*explain).to_string()),
+            ("expert", util::bool_to_on_or_off(*expert).to_string()),
+            ("headers", util::bool_to_on_or_off(*with_header).to_string()),
+            ("mode", mode),
+            (
+                "nullvalue",
+                format!(
+                    "\"{}\"",
+                    util::escape(null_value_repr.as_deref().unwrap_or(""))
+                ),
+            ),
+            ("output", output.to_string()),
+            (
+                "colseparator",
+                format!("\"{}\"", util::escape(&separators.column)),
+            ),
+            (
+                "rowseparator",
+                format!("\"{}\"", util::escape(&separators.row)),
+            ),
+            ("progress", progress.to_string()),
+            ("stats", <&str>::from(*stats).to_string()),
+            (
+                "timeout",
+                format!(
+                    "{} ms{}",
+                    timeout.ms,
+                    if timeout.verbose { " --verbose" } else { "" }
+                ),
+            ),
+            ("timer", util::bool_to_on_or_off(*with_timer).to_string()),
+            (
+                "trace",
+                trace
+                    .as_ref()
+                    .map_or("off".to_string(), ToString::to_string),
+            ),
+            ("width", widths.join(" ")),
+            ("sessions", session_names.join(" ")),
+            ("launcher", launcher.clone()),
+            ("cwd", cwd.display().to_string()),
+            ("filename", filename),
+        ];
+
+        let label_width = settings
+            .iter()
+            .map(|(label, _)| label.len())
+            .max()
+            .unwrap_or(0);
+        let lines: Vec<String> = settings
+            .iter()
+            .map(|(label, value)| format!("{:>width$}: {}", label, value, width = label_width))
+            .collect();
+
+        let writer: &mut dyn Write = match &mut self.ctx.output {
+            Output::BufferedStdout(out) => out,
+            Output::BufferedFile(f, _) => f,
+        };
+        for line in lines {
+            let _ = writeln!(writer, "{}", line);
+        }
+        let _ = writer.flush();
+        Ok(())
+    }
+    fn dot_stats(&mut self, args: &[&str]) -> DotResult {
+        // without an argument the counters of the connection are shown right away
         if args.is_empty() {
-            println!(".system needs at least an argument");
-            return;
+            let writer: &mut dyn Write = match &mut self.ctx.output {
+                Output::BufferedStdout(out) => out,
+                Output::BufferedFile(f, _) => f,
+            };
+            stats::print_connection_stats(writer, &self.ctx.conn.borrow());
+            let _ = writer.flush();
+            return Ok(());
+        }
+
+        match StatsMode::try_from(args[0]) {
+            Ok(stats) => self.ctx.stats = stats,
+            Err(_) => {
+                return Err(DotError::Usage(
+                    "Usage: .stats ?on|off|stmt|vmstep?".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+    fn dot_system(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(
+                ".system needs at least an argument".to_string(),
+            ));
         }
 
         let prog = args[0];
@@ -688,11 +1596,11 @@ impl<'a> CommandRunner<'a> {
         let output = Command::new(prog)
             .args(args)
             .output()
-            .expect("unable to get output");
+            .map_err(|e| DotError::Failed(format!("unable to run \"{}\": {}", prog, e)))?;
 
         let writer: &mut dyn Write = match &mut self.ctx.output {
             Output::BufferedStdout(out) => out,
-            Output::BufferedFile(f) => f,
+            Output::BufferedFile(f, _) => f,
         };
 
         let _ = write!(
@@ -707,8 +1615,9 @@ impl<'a> CommandRunner<'a> {
             str::from_utf8(&output.stderr)
                 .expect("unable to print output because theis one or more non-utf8 character")
         );
+        Ok(())
     }
-    fn dot_tables(&mut self, _args: &[&str]) -> rusqlite::Result<()> {
+    fn dot_tables(&mut self, _args: &[&str]) -> DotResult {
         let sql = "SELECT name FROM sqlite_schema WHERE type in ('table', 'view') AND name NOT LIKE 'sqlite_%' ORDER BY 1";
         let conn = self.ctx.conn.borrow();
         let mut stmt = conn.prepare(sql)?;
@@ -717,6 +1626,7 @@ impl<'a> CommandRunner<'a> {
         let title = util::query_title_row(&mut stmt, col_count, self.ctx.mode)?;
         let table_names = util::query_data_rows(
             &mut stmt,
+            (),
             col_count,
             self.ctx.mode,
             self.ctx.null_value_repr.as_ref(),
@@ -728,15 +1638,60 @@ impl<'a> CommandRunner<'a> {
             title,
             table_names,
             true,
+            &self.ctx.columnar,
+            &self.ctx.separators,
         );
 
         Ok(())
     }
-    fn dot_timeout(&mut self, _args: &[&str]) {}
-    fn dot_timer(&mut self, _args: &[&str]) {}
-    fn dot_trace(&mut self, _args: &[&str]) {}
-    fn dot_unmodule(&mut self, _args: &[&str]) {}
-    fn dot_version(&mut self, _args: &[&str]) {
+    fn dot_timeout(&mut self, args: &[&str]) -> DotResult {
+        match TimeoutOptions::parse(args) {
+            Ok(opts) => {
+                self.ctx.timeout = opts;
+                busy::install(&self.ctx.conn.borrow(), opts);
+            }
+            Err(msg) => return Err(DotError::Usage(msg)),
+        }
+        Ok(())
+    }
+    fn dot_timer(&mut self, args: &[&str]) -> DotResult {
+        if args.is_empty() {
+            return Err(DotError::Usage(".timer needs an argument".to_string()));
+        }
+
+        self.ctx.with_timer = util::on_or_off_to_bool(args[0]);
+        Ok(())
+    }
+    fn dot_trace(&mut self, args: &[&str]) -> DotResult {
+        let opts = TraceOptions::parse(args).map_err(DotError::Failed)?;
+        self.ctx.trace = trace::configure(
+            &self.ctx.conn.borrow(),
+            opts,
+            &self.ctx.cwd,
+            self.ctx.trace.as_ref(),
+        )
+        .map_err(DotError::Failed)?;
+        Ok(())
+    }
+    fn dot_unmodule(&mut self, args: &[&str]) -> DotResult {
+        let args: Vec<&str> = args.iter().copied().filter(|arg| !arg.is_empty()).collect();
+        let conn = self.ctx.conn.borrow();
+
+        let result = match args[..] {
+            [] => {
+                return Err(DotError::Usage(
+                    "Usage: .unmodule ?--allexcept? NAME ...".to_string(),
+                ));
+            }
+            ["--allexcept" | "-allexcept", ref keep @ ..] => util::drop_modules_except(&conn, keep),
+            ref names => names
+                .iter()
+                .try_for_each(|name| util::drop_module(&conn, name)),
+        };
+
+        result.map_err(DotError::Failed)
+    }
+    fn dot_version(&mut self, _args: &[&str]) -> DotResult {
         let version: &str = SQLITE_VERSION
             .to_str()
             .expect("version string has a non-utf 8 character");
@@ -756,10 +1711,54 @@ impl<'a> CommandRunner<'a> {
         table.set_format(fmt);
 
         self.ctx.output.print_prettytable(&mut table);
+        Ok(())
+    }
+    fn dot_vfsinfo(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_vfslist(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_vfsname(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+    fn dot_width(&mut self, args: &[&str]) -> DotResult {
+        let widths = args
+            .iter()
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.parse::<i32>())
+            .collect::<Result<Vec<_>, _>>();
+
+        // without arguments every column is sized by its content again
+        match widths {
+            Ok(widths) => self.ctx.columnar.widths = widths,
+            Err(e) => return Err(DotError::Usage(format!("invalid width: {}", e))),
+        }
+        Ok(())
+    }
+    fn dot_www(&mut self, _args: &[&str]) -> DotResult {
+        Ok(())
+    }
+}
+
+fn report_error(err: &rusqlite::Error, location: Option<&str>) {
+    let prefix = match location {
+        Some(location) => format!("ERROR: {}:", location),
+        None => "ERROR:".to_string(),
+    };
+
+    match err {
+        RSQE::SqlInputError { msg, sql, .. } => {
+            eprintln!("{} {} is in invalid sqlite query", prefix, sql);
+            eprintln!("{}", msg);
+        }
+        RSQE::SqliteFailure(_, msg) => {
+            eprintln!(
+                "{} {}",
+                prefix,
+                msg.as_deref().unwrap_or("something bad happended")
+            );
+        }
+        _ => eprintln!("{} {}", prefix, err),
     }
-    fn dot_vfsinfo(&mut self, _args: &[&str]) {}
-    fn dot_vfslist(&mut self, _args: &[&str]) {}
-    fn dot_vfsname(&mut self, _args: &[&str]) {}
-    fn dot_width(&mut self, _args: &[&str]) {}
-    fn dot_www(&mut self, _args: &[&str]) {}
 }
@@ -0,0 +1,171 @@
+use crate::util;
+use std::io::{self, BufRead, Lines};
+
+#[derive(Debug)]
+pub struct ScriptEntry {
+    pub line: usize,
+    pub text: String,
+}
+
+// a dot command has to start a line of its own, SQL lines are collected until SQLite
+// considers them complete
+pub struct ScriptReader<R> {
+    lines: Lines<R>,
+    line_no: usize,
+    pending: Vec<ScriptEntry>,
+}
+
+impl<R: BufRead> ScriptReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_no: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    // the entries are queued in reverse order, so that `pop` hands them out in order
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut sql = String::new();
+        let mut start_line = 0;
+        let mut more = false;
+
+        for line in self.lines.by_ref() {
+            let line = line?;
+            self.line_no += 1;
+
+            // comments between statements don't keep a dot command from starting
+            if is_blank_sql(&sql) {
+                sql.clear();
+            }
+            if sql.is_empty() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                start_line = self.line_no;
+                if line.starts_with('.') {
+                    self.pending.push(ScriptEntry {
+                        line: start_line,
+                        text: line.trim_end().to_string(),
+                    });
+                    return Ok(true);
+                }
+            }
+
+            sql.push_str(&line);
+            sql.push('\n');
+            if util::is_complete_sql(&sql) {
+                more = true;
+                break;
+            }
+        }
+
+        if is_blank_sql(&sql) {
+            return Ok(more);
+        }
+        // the last statement of a script doesn't need a semicolon
+        for statement in util::split_statements(&sql).into_iter().rev() {
+            let offset = statement.as_ptr() as usize - sql.as_ptr() as usize;
+            self.pending.push(ScriptEntry {
+                line: start_line + sql[..offset].matches('\n').count(),
+                text: statement.to_string(),
+            });
+        }
+
+        Ok(more)
+    }
+}
+
+impl<R: BufRead> Iterator for ScriptReader<R> {
+    type Item = io::Result<ScriptEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // input like a lone `;` doesn't produce an entry, so this may take several rounds
+        while self.pending.is_empty() {
+            match self.fill() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        self.pending.pop().map(Ok)
+    }
+}
+
+fn is_blank_sql(sql: &str) -> bool {
+    let mut rest = sql.trim_start();
+    loop {
+        if let Some(comment) = rest.strip_prefix("--") {
+            match comment.find('\n') {
+                Some(end) => rest = comment[end..].trim_start(),
+                None => return true,
+            }
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(end) => rest = comment[end + 2..].trim_start(),
+                None => return false,
+            }
+        } else {
+            return rest.is_empty();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(script: &str) -> Vec<(usize, String)> {
+        ScriptReader::new(script.as_bytes())
+            .map(|entry| entry.map(|entry| (entry.line, entry.text)))
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_dot_commands_and_statements_with_their_lines() {
+        let script = ".mode csv\nSELECT 1;\n\nSELECT\n  2;\n.headers on\n";
+        assert_eq!(
+            entries(script),
+            [
+                (1, ".mode csv".to_string()),
+                (2, "SELECT 1;".to_string()),
+                (4, "SELECT\n  2;".to_string()),
+                (6, ".headers on".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_statements_that_share_a_line() {
+        assert_eq!(
+            entries("SELECT 1; SELECT\n2;"),
+            [(1, "SELECT 1;".to_string()), (1, "SELECT\n2;".to_string())]
+        );
+    }
+
+    #[test]
+    fn a_dot_inside_a_statement_is_not_a_command() {
+        assert_eq!(
+            entries("SELECT 'a\n.b';\n"),
+            [(1, "SELECT 'a\n.b';".to_string())]
+        );
+    }
+
+    #[test]
+    fn comments_between_statements_are_skipped() {
+        assert_eq!(
+            entries("-- leading comment\n.tables\n/* block */\nSELECT 1;\n"),
+            [(2, ".tables".to_string()), (4, "SELECT 1;".to_string())]
+        );
+    }
+
+    #[test]
+    fn the_last_statement_needs_no_semicolon() {
+        assert_eq!(
+            entries("SELECT 1;\nSELECT 2\n"),
+            [(1, "SELECT 1;".to_string()), (2, "SELECT 2".to_string())]
+        );
+    }
+}
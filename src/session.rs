@@ -0,0 +1,310 @@
+use crate::util;
+use rusqlite::Connection;
+use rusqlite::ffi;
+use rusqlite::types::ValueRef;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::ptr;
+
+// its own allocation, because the table filter callback keeps a pointer to it
+struct TableFilter {
+    globs: Vec<CString>,
+}
+
+pub struct Session {
+    pub name: String,
+    raw: *mut ffi::sqlite3_session,
+    filter: Box<TableFilter>,
+}
+
+impl Session {
+    // the session has to be dropped before `conn` is closed
+    pub fn open(conn: &Connection, db: &str, name: &str) -> Result<Self, String> {
+        let db = CString::new(db).map_err(|e| e.to_string())?;
+        let mut raw = ptr::null_mut();
+        // SAFETY: `conn.handle()` is an open connection that outlives the session, the shell
+        // drops its sessions before it closes or replaces the connection. `db` is
+        // NUL-terminated and lives until the call returns
+        let rc = unsafe { ffi::sqlite3session_create(conn.handle(), db.as_ptr(), &mut raw) };
+        if rc != ffi::SQLITE_OK {
+            return Err(format!("cannot open session: {}", error_string(rc)));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            raw,
+            filter: Box::new(TableFilter { globs: Vec::new() }),
+        })
+    }
+
+    pub fn attach(&mut self, table: Option<&str>) -> Result<(), String> {
+        let table = table
+            .map(CString::new)
+            .transpose()
+            .map_err(|e| e.to_string())?;
+        let table_ptr = table.as_ref().map_or(ptr::null(), |table| table.as_ptr());
+        // SAFETY: `self.raw` is a live session until `drop`. `table_ptr` is NULL or points
+        // into `table`, which outlives the call, SQLite copies the name
+        let rc = unsafe { ffi::sqlite3session_attach(self.raw, table_ptr) };
+        if rc != ffi::SQLITE_OK {
+            return Err(format!("cannot attach table: {}", error_string(rc)));
+        }
+
+        Ok(())
+    }
+
+    pub fn changes(&mut self, patchset: bool) -> Result<Vec<u8>, String> {
+        let mut len = 0;
+        let mut data = ptr::null_mut();
+        // SAFETY: `self.raw` is a live session, `len` and `data` are valid out-pointers
+        let rc = unsafe {
+            if patchset {
+                ffi::sqlite3session_patchset(self.raw, &mut len, &mut data)
+            } else {
+                ffi::sqlite3session_changeset(self.raw, &mut len, &mut data)
+            }
+        };
+        if rc != ffi::SQLITE_OK {
+            return Err(error_string(rc));
+        }
+
+        let changes = if data.is_null() {
+            Vec::new()
+        } else {
+            // SAFETY: on success SQLite hands over a buffer of `len` bytes at `data`. It is
+            // copied before it is freed, and freed exactly once with `sqlite3_free`
+            let changes =
+                unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) }.to_vec();
+            unsafe { ffi::sqlite3_free(data) };
+            changes
+        };
+
+        Ok(changes)
+    }
+
+    pub fn set_filter(&mut self, globs: &[&str]) -> Result<(), String> {
+        self.filter.globs = globs
+            .iter()
+            .map(|glob| CString::new(*glob))
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let filter_ptr = &*self.filter as *const TableFilter as *mut c_void;
+        // SAFETY: `self.raw` is a live session. The filter is boxed, so `filter_ptr` stays
+        // valid for as long as the session can call `table_filter` with it, both are
+        // dropped together
+        unsafe { ffi::sqlite3session_table_filter(self.raw, Some(table_filter), filter_ptr) };
+
+        Ok(())
+    }
+
+    pub fn enable(&mut self, enabled: Option<bool>) -> bool {
+        let flag = enabled.map_or(-1, c_int::from);
+        // SAFETY: `self.raw` is a live session until `drop`
+        unsafe { ffi::sqlite3session_enable(self.raw, flag) != 0 }
+    }
+
+    pub fn indirect(&mut self, indirect: Option<bool>) -> bool {
+        let flag = indirect.map_or(-1, c_int::from);
+        // SAFETY: `self.raw` is a live session until `drop`
+        unsafe { ffi::sqlite3session_indirect(self.raw, flag) != 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // SAFETY: `self.raw` is a live session until `drop`
+        unsafe { ffi::sqlite3session_isempty(self.raw) != 0 }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // SAFETY: `self.raw` was created by `sqlite3session_create` and is deleted only here,
+        // while its connection is still open
+        unsafe { ffi::sqlite3session_delete(self.raw) };
+    }
+}
+
+extern "C" fn table_filter(ctx: *mut c_void, table: *const c_char) -> c_int {
+    // SAFETY: `ctx` is the boxed `TableFilter` that `set_filter` registered, it lives as
+    // long as the session that calls this
+    let filter = unsafe { &*(ctx as *const TableFilter) };
+    let ignored = filter
+        .globs
+        .iter()
+        // SAFETY: both are NUL-terminated, `table` is valid for the duration of the callback
+        .any(|glob| unsafe { ffi::sqlite3_strglob(glob.as_ptr(), table) } == 0);
+
+    c_int::from(!ignored)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    Omit,
+    Replace,
+    Abort,
+}
+
+struct ApplyState {
+    policy: ConflictPolicy,
+    conflicts: usize,
+}
+
+pub fn apply_changeset(
+    conn: &Connection,
+    changeset: &[u8],
+    policy: ConflictPolicy,
+) -> Result<usize, String> {
+    let mut state = ApplyState {
+        policy,
+        conflicts: 0,
+    };
+
+    // SAFETY: `conn.handle()` is an open connection. SQLite only reads the changeset, which
+    // stays borrowed until the call returns. `state` outlives the call, which is the only
+    // time `on_conflict` receives a pointer to it
+    let rc = unsafe {
+        ffi::sqlite3changeset_apply(
+            conn.handle(),
+            changeset.len() as c_int,
+            changeset.as_ptr() as *mut c_void,
+            None,
+            Some(on_conflict),
+            &mut state as *mut ApplyState as *mut c_void,
+        )
+    };
+
+    match rc {
+        ffi::SQLITE_OK => Ok(state.conflicts),
+        ffi::SQLITE_ABORT => Err(format!(
+            "aborted after {} conflict(s), nothing was applied",
+            state.conflicts
+        )),
+        _ => Err(error_string(rc)),
+    }
+}
+
+extern "C" fn on_conflict(
+    ctx: *mut c_void,
+    conflict: c_int,
+    iter: *mut ffi::sqlite3_changeset_iter,
+) -> c_int {
+    // SAFETY: `ctx` is the `ApplyState` that `apply_changeset` passed in and nothing else
+    // accesses it while the callback runs
+    let state = unsafe { &mut *(ctx as *mut ApplyState) };
+    state.conflicts += 1;
+
+    // the iterator of a foreign key conflict only knows how many violations there are
+    if conflict == ffi::SQLITE_CHANGESET_FOREIGN_KEY {
+        let mut count = 0;
+        // SAFETY: `iter` is the iterator SQLite passes to this conflict callback
+        unsafe { ffi::sqlite3changeset_fk_conflicts(iter, &mut count) };
+        eprintln!("conflict: {} foreign key violation(s)", count);
+    } else {
+        let reason = match conflict {
+            ffi::SQLITE_CHANGESET_DATA => "row has changed",
+            ffi::SQLITE_CHANGESET_NOTFOUND => "row not found",
+            ffi::SQLITE_CHANGESET_CONFLICT => "row already exists",
+            _ => "constraint violation",
+        };
+        eprintln!("conflict: {}: {}", describe_change(iter), reason);
+    }
+
+    match (state.policy, conflict) {
+        (ConflictPolicy::Abort, _) => ffi::SQLITE_CHANGESET_ABORT,
+        // the other conflicts can't be resolved by replacing the row
+        (ConflictPolicy::Replace, ffi::SQLITE_CHANGESET_DATA | ffi::SQLITE_CHANGESET_CONFLICT) => {
+            ffi::SQLITE_CHANGESET_REPLACE
+        }
+        _ => ffi::SQLITE_CHANGESET_OMIT,
+    }
+}
+
+fn describe_change(iter: *mut ffi::sqlite3_changeset_iter) -> String {
+    let mut table: *const c_char = ptr::null();
+    let mut col_count = 0;
+    let mut op = 0;
+    let mut indirect = 0;
+    // SAFETY: `iter` points at a change during a conflict callback, the rest are valid
+    // out-pointers
+    let rc = unsafe {
+        ffi::sqlite3changeset_op(iter, &mut table, &mut col_count, &mut op, &mut indirect)
+    };
+    if rc != ffi::SQLITE_OK || table.is_null() {
+        return "unknown change".to_string();
+    }
+    // SAFETY: SQLite returned a non-NULL, NUL-terminated name that lives as long as `iter`
+    let table = unsafe { CStr::from_ptr(table) }.to_string_lossy();
+
+    let mut pk_flags: *mut u8 = ptr::null_mut();
+    // SAFETY: `iter` points at a change, `pk_flags` is a valid out-pointer and the column
+    // count may be left out
+    unsafe { ffi::sqlite3changeset_pk(iter, &mut pk_flags, ptr::null_mut()) };
+    let pk_values: Vec<String> = (0..col_count)
+        // SAFETY: `pk_flags` holds one flag for each of the `col_count` columns
+        .filter(|idx| !pk_flags.is_null() && unsafe { *pk_flags.add(*idx as usize) } != 0)
+        .map(|idx| {
+            let mut value = ptr::null_mut();
+            // SAFETY: `idx` is a column of the change, new values exist for inserts and old
+            // ones for updates and deletes
+            unsafe {
+                if op == ffi::SQLITE_INSERT {
+                    ffi::sqlite3changeset_new(iter, idx, &mut value);
+                } else {
+                    ffi::sqlite3changeset_old(iter, idx, &mut value);
+                }
+            }
+            sql_value_literal(value)
+        })
+        .collect();
+
+    let op = match op {
+        ffi::SQLITE_INSERT => "INSERT",
+        ffi::SQLITE_DELETE => "DELETE",
+        _ => "UPDATE",
+    };
+
+    format!("{} {}({})", op, table, pk_values.join(","))
+}
+
+fn sql_value_literal(value: *mut ffi::sqlite3_value) -> String {
+    if value.is_null() {
+        return "NULL".to_string();
+    }
+
+    // SAFETY: `value` is a non-NULL value of the current change, it stays valid while the
+    // conflict callback runs and is only read here
+    unsafe {
+        let value = match ffi::sqlite3_value_type(value) {
+            ffi::SQLITE_INTEGER => ValueRef::Integer(ffi::sqlite3_value_int64(value)),
+            ffi::SQLITE_FLOAT => ValueRef::Real(ffi::sqlite3_value_double(value)),
+            ffi::SQLITE_TEXT => {
+                let text = ffi::sqlite3_value_text(value);
+                ValueRef::Text(value_bytes(value, text))
+            }
+            ffi::SQLITE_BLOB => {
+                let blob = ffi::sqlite3_value_blob(value) as *const u8;
+                ValueRef::Blob(value_bytes(value, blob))
+            }
+            _ => ValueRef::Null,
+        };
+        util::sql_literal(value, false)
+    }
+}
+
+// `data` has to be the text or blob pointer of `value`
+unsafe fn value_bytes<'a>(value: *mut ffi::sqlite3_value, data: *const u8) -> &'a [u8] {
+    if data.is_null() {
+        return &[];
+    }
+    // SAFETY: `data` belongs to `value` and holds `sqlite3_value_bytes` bytes
+    unsafe {
+        let len = ffi::sqlite3_value_bytes(value) as usize;
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+fn error_string(rc: c_int) -> String {
+    // SAFETY: `sqlite3_errstr` returns a static NUL-terminated string for any code
+    unsafe { CStr::from_ptr(ffi::sqlite3_errstr(rc)) }
+        .to_string_lossy()
+        .to_string()
+}
@@ -0,0 +1,180 @@
+use crate::util;
+use rusqlite::Connection;
+use rusqlite::types::ValueRef;
+use sha3::digest::DynDigest;
+use sha3::{Sha3_224, Sha3_256, Sha3_384, Sha3_512};
+
+#[derive(Debug)]
+pub struct Sha3sumOptions {
+    pub schema: bool,
+    pub size: usize,
+    pub debug: bool,
+    pub pattern: Option<String>,
+}
+
+impl Sha3sumOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut opts = Self {
+            schema: false,
+            size: 224,
+            debug: false,
+            pattern: None,
+        };
+
+        for &arg in args.iter().filter(|arg| !arg.is_empty()) {
+            if let Some(option) = arg.strip_prefix('-') {
+                match option.strip_prefix('-').unwrap_or(option) {
+                    "schema" => opts.schema = true,
+                    "sha3-224" => opts.size = 224,
+                    "sha3-256" => opts.size = 256,
+                    "sha3-384" => opts.size = 384,
+                    "sha3-512" => opts.size = 512,
+                    "debug" => opts.debug = true,
+                    _ => return Err(format!("unknown option: {}", arg)),
+                }
+            } else if opts.pattern.is_some() {
+                return Err("Usage: .sha3sum ?OPTIONS? ?LIKE-PATTERN?".to_string());
+            } else {
+                // a pattern that names internal tables is pointless without them
+                if arg.to_lowercase().starts_with("sqlite_") {
+                    opts.schema = true;
+                }
+                opts.pattern = Some(arg.to_string());
+            }
+        }
+
+        Ok(opts)
+    }
+
+    fn hasher(&self) -> Box<dyn DynDigest> {
+        match self.size {
+            256 => Box::new(Sha3_256::default()),
+            384 => Box::new(Sha3_384::default()),
+            512 => Box::new(Sha3_512::default()),
+            _ => Box::new(Sha3_224::default()),
+        }
+    }
+}
+
+struct HashedQuery {
+    label: String,
+    sql: String,
+}
+
+// the hashes match the ones of `.sha3sum` in the `sqlite3` shell
+pub fn hash_database(
+    conn: &Connection,
+    opts: &Sha3sumOptions,
+) -> rusqlite::Result<Vec<(String, Option<String>)>> {
+    let queries = hashed_queries(conn, opts)?;
+
+    if opts.pattern.is_none() {
+        let mut hasher = opts.hasher();
+        for query in &queries {
+            hash_query(conn, &query.sql, hasher.as_mut())?;
+        }
+        return Ok(vec![(hex(&hasher.finalize()), None)]);
+    }
+
+    queries
+        .into_iter()
+        .map(|query| {
+            let mut hasher = opts.hasher();
+            hash_query(conn, &query.sql, hasher.as_mut())?;
+            Ok((hex(&hasher.finalize()), Some(query.label)))
+        })
+        .collect()
+}
+
+pub fn debug_queries(conn: &Connection, opts: &Sha3sumOptions) -> rusqlite::Result<Vec<String>> {
+    let queries = hashed_queries(conn, opts)?;
+    Ok(queries.into_iter().map(|query| query.sql).collect())
+}
+
+// tables are scanned without an index, so the rows come in rowid or primary key order
+fn hashed_queries(conn: &Connection, opts: &Sha3sumOptions) -> rusqlite::Result<Vec<HashedQuery>> {
+    let tables_sql = if opts.schema {
+        "SELECT lower(name) AS tname FROM sqlite_schema \
+         WHERE type='table' AND coalesce(rootpage,0)>1 \
+         UNION ALL SELECT 'sqlite_schema' \
+         ORDER BY 1 COLLATE NOCASE"
+    } else {
+        "SELECT lower(name) AS tname FROM sqlite_schema \
+         WHERE type='table' AND coalesce(rootpage,0)>1 AND name NOT LIKE 'sqlite_%' \
+         ORDER BY 1 COLLATE NOCASE"
+    };
+    let mut stmt = conn.prepare(tables_sql)?;
+    let tables = stmt
+        .query_map((), |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut queries = Vec::with_capacity(tables.len());
+    for table in tables {
+        if let Some(pattern) = &opts.pattern {
+            let matches = conn.query_row("SELECT ?1 LIKE ?2", (&table, pattern), |row| {
+                row.get::<_, bool>(0)
+            })?;
+            if !matches {
+                continue;
+            }
+        }
+
+        let sql = match table.as_str() {
+            "sqlite_schema" => {
+                "SELECT type,name,tbl_name,sql FROM sqlite_schema ORDER BY name;".to_string()
+            }
+            "sqlite_sequence" => "SELECT name,seq FROM sqlite_sequence ORDER BY name;".to_string(),
+            "sqlite_stat1" => "SELECT tbl,idx,stat FROM sqlite_stat1 ORDER BY tbl,idx;".to_string(),
+            "sqlite_stat4" => "SELECT * FROM sqlite_stat4 ORDER BY tbl, idx, rowid;".to_string(),
+            _ if table.starts_with("sqlite_") => continue,
+            _ => format!(
+                "SELECT * FROM {} NOT INDEXED;",
+                util::quote_identifier(&table)
+            ),
+        };
+        queries.push(HashedQuery { label: table, sql });
+    }
+
+    Ok(queries)
+}
+
+// the encoding matches `sha3_query()` of the `sqlite3` shell
+fn hash_query(conn: &Connection, sql: &str, hasher: &mut dyn DynDigest) -> rusqlite::Result<()> {
+    hasher.update(format!("S{}:", sql.len()).as_bytes());
+    hasher.update(sql.as_bytes());
+
+    let mut stmt = conn.prepare(sql)?;
+    let col_count = stmt.column_count();
+    let mut rows = stmt.query(())?;
+
+    while let Some(row) = rows.next()? {
+        hasher.update(b"R");
+        for col_idx in 0..col_count {
+            match row.get_ref(col_idx)? {
+                ValueRef::Null => hasher.update(b"N"),
+                ValueRef::Integer(int) => {
+                    hasher.update(b"I");
+                    hasher.update(&int.to_be_bytes());
+                }
+                ValueRef::Real(fp) => {
+                    hasher.update(b"F");
+                    hasher.update(&fp.to_bits().to_be_bytes());
+                }
+                ValueRef::Text(text) => {
+                    hasher.update(format!("T{}:", text.len()).as_bytes());
+                    hasher.update(text);
+                }
+                ValueRef::Blob(blob) => {
+                    hasher.update(format!("B{}:", blob.len()).as_bytes());
+                    hasher.update(blob);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,141 @@
+use rusqlite::{Connection, Statement, StatementStatus, ffi};
+use std::io::Write;
+
+// so that the numbers line up like in the `sqlite3` shell
+const LABEL_WIDTH: usize = 37;
+
+fn write_line(writer: &mut dyn Write, label: &str, value: String) {
+    let _ = writeln!(
+        writer,
+        "{:<width$}{}",
+        format!("{}:", label),
+        value,
+        width = LABEL_WIDTH
+    );
+}
+
+fn process_status(op: i32) -> (i64, i64) {
+    let mut current = 0;
+    let mut highwater = 0;
+    // SAFETY: sqlite3_status64 only writes the two counters behind the pointers, which are
+    // valid locals. An unknown `op` is reported as SQLITE_MISUSE and leaves them at 0
+    unsafe { ffi::sqlite3_status64(op, &mut current, &mut highwater, 0) };
+    (current, highwater)
+}
+
+// `reset` turns the cache counters into counts of the last statement
+fn db_status(conn: &Connection, op: i32, reset: bool) -> (i32, i32) {
+    let mut current = 0;
+    let mut highwater = 0;
+    // SAFETY: `conn.handle()` is an open connection for as long as `conn` is borrowed, and
+    // sqlite3_db_status only writes the two counters behind the pointers, which are valid locals
+    unsafe {
+        ffi::sqlite3_db_status(
+            conn.handle(),
+            op,
+            &mut current,
+            &mut highwater,
+            i32::from(reset),
+        )
+    };
+    (current, highwater)
+}
+
+pub fn print_connection_stats(writer: &mut dyn Write, conn: &Connection) {
+    let (used, max_used) = process_status(ffi::SQLITE_STATUS_MEMORY_USED);
+    write_line(
+        writer,
+        "Memory Used",
+        format!("{} (max {}) bytes", used, max_used),
+    );
+    let (allocs, max_allocs) = process_status(ffi::SQLITE_STATUS_MALLOC_COUNT);
+    write_line(
+        writer,
+        "Number of Outstanding Allocations",
+        format!("{} (max {})", allocs, max_allocs),
+    );
+    let (overflow, max_overflow) = process_status(ffi::SQLITE_STATUS_PAGECACHE_OVERFLOW);
+    write_line(
+        writer,
+        "Number of Pcache Overflow Bytes",
+        format!("{} (max {}) bytes", overflow, max_overflow),
+    );
+    let (_, largest) = process_status(ffi::SQLITE_STATUS_MALLOC_SIZE);
+    write_line(writer, "Largest Allocation", format!("{} bytes", largest));
+    let (_, largest_pcache) = process_status(ffi::SQLITE_STATUS_PAGECACHE_SIZE);
+    write_line(
+        writer,
+        "Largest Pcache Allocation",
+        format!("{} bytes", largest_pcache),
+    );
+
+    let (slots, max_slots) = db_status(conn, ffi::SQLITE_DBSTATUS_LOOKASIDE_USED, false);
+    write_line(
+        writer,
+        "Lookaside Slots Used",
+        format!("{} (max {})", slots, max_slots),
+    );
+    let lookaside_counters = [
+        (
+            ffi::SQLITE_DBSTATUS_LOOKASIDE_HIT,
+            "Successful lookaside attempts",
+        ),
+        (
+            ffi::SQLITE_DBSTATUS_LOOKASIDE_MISS_SIZE,
+            "Lookaside failures due to size",
+        ),
+        (
+            ffi::SQLITE_DBSTATUS_LOOKASIDE_MISS_FULL,
+            "Lookaside failures due to OOM",
+        ),
+    ];
+    for (op, label) in lookaside_counters {
+        let (_, count) = db_status(conn, op, false);
+        write_line(writer, label, count.to_string());
+    }
+
+    let (pager, _) = db_status(conn, ffi::SQLITE_DBSTATUS_CACHE_USED, false);
+    write_line(writer, "Pager Heap Usage", format!("{} bytes", pager));
+    let cache_counters = [
+        (ffi::SQLITE_DBSTATUS_CACHE_HIT, "Page cache hits"),
+        (ffi::SQLITE_DBSTATUS_CACHE_MISS, "Page cache misses"),
+        (ffi::SQLITE_DBSTATUS_CACHE_WRITE, "Page cache writes"),
+        (ffi::SQLITE_DBSTATUS_CACHE_SPILL, "Page cache spills"),
+    ];
+    for (op, label) in cache_counters {
+        let (count, _) = db_status(conn, op, true);
+        write_line(writer, label, count.to_string());
+    }
+
+    let (schema, _) = db_status(conn, ffi::SQLITE_DBSTATUS_SCHEMA_USED, false);
+    write_line(writer, "Schema Heap Usage", format!("{} bytes", schema));
+    let (stmts, _) = db_status(conn, ffi::SQLITE_DBSTATUS_STMT_USED, false);
+    write_line(
+        writer,
+        "Statement Heap/Lookaside Usage",
+        format!("{} bytes", stmts),
+    );
+}
+
+pub fn print_statement_stats(writer: &mut dyn Write, stmt: &Statement) {
+    let counters = [
+        (StatementStatus::FullscanStep, "Fullscan Steps"),
+        (StatementStatus::Sort, "Sort Operations"),
+        (StatementStatus::AutoIndex, "Autoindex Inserts"),
+        (StatementStatus::VmStep, "Virtual Machine Steps"),
+        (StatementStatus::RePrepare, "Reprepare operations"),
+        (StatementStatus::Run, "Number of times run"),
+        (StatementStatus::MemUsed, "Memory used by prepared stmt"),
+    ];
+    for (status, label) in counters {
+        write_line(writer, label, stmt.get_status(status).to_string());
+    }
+}
+
+pub fn print_vm_steps(writer: &mut dyn Write, stmt: &Statement) {
+    let _ = writeln!(
+        writer,
+        "VM-steps: {}",
+        stmt.get_status(StatementStatus::VmStep)
+    );
+}
@@ -0,0 +1,200 @@
+use rusqlite::Connection;
+use rusqlite::trace::{TraceEvent, TraceEventCodes};
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{Write, stderr};
+use std::path::Path;
+
+thread_local! {
+    // `trace_v2` only accepts a plain function, so the tracer that it writes to lives here
+    static TRACER: RefCell<Option<Tracer>> = const { RefCell::new(None) };
+}
+
+struct Tracer {
+    sink: Sink,
+    expanded: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceSettings {
+    target: String,
+    expanded: bool,
+    events: TraceEventCodes,
+}
+
+enum Sink {
+    // lines for stdout wait here until the shell writes them to its current output, that way
+    // they stay in order with the results, which are buffered
+    Output(Vec<String>),
+    Writer(Box<dyn Write>),
+}
+
+#[derive(Debug)]
+pub struct TraceOptions {
+    pub target: Option<String>,
+    pub expanded: Option<bool>,
+    pub events: TraceEventCodes,
+}
+
+impl TraceOptions {
+    pub fn parse(args: &[&str]) -> Result<Self, String> {
+        let mut opts = Self {
+            target: None,
+            expanded: None,
+            events: TraceEventCodes::empty(),
+        };
+
+        for &arg in args.iter().filter(|arg| !arg.is_empty()) {
+            match arg {
+                "--plain" => opts.expanded = Some(false),
+                "--expanded" => opts.expanded = Some(true),
+                "--stmt" => opts.events |= TraceEventCodes::SQLITE_TRACE_STMT,
+                "--profile" => opts.events |= TraceEventCodes::SQLITE_TRACE_PROFILE,
+                "--row" => opts.events |= TraceEventCodes::SQLITE_TRACE_ROW,
+                "--close" => opts.events |= TraceEventCodes::SQLITE_TRACE_CLOSE,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => opts.target = Some(arg.to_string()),
+            }
+        }
+
+        Ok(opts)
+    }
+}
+
+// without a target, the `current` trace keeps its destination and a new one goes to
+// stdout. Returns `None` once the trace is off
+pub fn configure(
+    conn: &Connection,
+    opts: TraceOptions,
+    cwd: &Path,
+    current: Option<&TraceSettings>,
+) -> Result<Option<TraceSettings>, String> {
+    let sink = match opts.target.as_deref() {
+        Some("off") => {
+            TRACER.with_borrow_mut(|tracer| *tracer = None);
+            install(conn, None);
+            return Ok(None);
+        }
+        Some("stdout") => Some(Sink::Output(Vec::new())),
+        Some("stderr") => Some(Sink::Writer(Box::new(stderr()))),
+        Some(file) => {
+            let file = File::create(cwd.join(file))
+                .map_err(|e| format!("cannot open \"{}\": {}", file, e))?;
+            Some(Sink::Writer(Box::new(file)))
+        }
+        None => None,
+    };
+
+    let expanded = opts
+        .expanded
+        .or(current.map(|current| current.expanded))
+        .unwrap_or(true);
+    let events = if opts.events.is_empty() {
+        TraceEventCodes::SQLITE_TRACE_STMT
+    } else {
+        opts.events
+    };
+    let target = TRACER.with_borrow_mut(|tracer| {
+        let previous = tracer.take();
+        let (sink, target) = match (sink, previous, current) {
+            (Some(sink), _, _) => (sink, opts.target.unwrap_or_default()),
+            (None, Some(previous), Some(current)) => (previous.sink, current.target.clone()),
+            _ => (Sink::Output(Vec::new()), "stdout".to_string()),
+        };
+        *tracer = Some(Tracer { sink, expanded });
+        target
+    });
+
+    let settings = TraceSettings {
+        target,
+        expanded,
+        events,
+    };
+    install(conn, Some(&settings));
+    Ok(Some(settings))
+}
+
+// the trace the way `.trace` would be given it
+impl fmt::Display for TraceSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.target)?;
+        f.write_str(if self.expanded {
+            " --expanded"
+        } else {
+            " --plain"
+        })?;
+        for (event, option) in [
+            (TraceEventCodes::SQLITE_TRACE_STMT, " --stmt"),
+            (TraceEventCodes::SQLITE_TRACE_PROFILE, " --profile"),
+            (TraceEventCodes::SQLITE_TRACE_ROW, " --row"),
+            (TraceEventCodes::SQLITE_TRACE_CLOSE, " --close"),
+        ] {
+            if self.events.contains(event) {
+                f.write_str(option)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn write_pending(writer: &mut dyn Write) {
+    TRACER.with_borrow_mut(|tracer| {
+        if let Some(Tracer {
+            sink: Sink::Output(pending),
+            ..
+        }) = tracer
+            && !pending.is_empty()
+        {
+            for line in pending.drain(..) {
+                let _ = writeln!(writer, "{}", line);
+            }
+            let _ = writer.flush();
+        }
+    });
+}
+
+// this is needed after `.open` replaced the connection
+pub fn install(conn: &Connection, settings: Option<&TraceSettings>) {
+    match settings {
+        Some(settings) => conn.trace_v2(settings.events, Some(write_event)),
+        None => conn.trace_v2(TraceEventCodes::empty(), None),
+    }
+}
+
+fn write_event(event: TraceEvent<'_>) {
+    TRACER.with_borrow_mut(|tracer| {
+        let Some(tracer) = tracer else {
+            return;
+        };
+
+        let sql = |stmt: &rusqlite::trace::StmtRef<'_>| {
+            let sql = if tracer.expanded {
+                stmt.expanded_sql()
+                    .unwrap_or_else(|| stmt.sql().to_string())
+            } else {
+                stmt.sql().to_string()
+            };
+            sql.trim_end_matches(';').to_string()
+        };
+
+        let line = match event {
+            // the start of a trigger is reported as a comment that names it
+            TraceEvent::Stmt(_, text) if text.starts_with("--") => text.to_string(),
+            TraceEvent::Stmt(stmt, _) | TraceEvent::Row(stmt) => format!("{};", sql(&stmt)),
+            TraceEvent::Profile(stmt, elapsed) => {
+                format!("{}; -- {} ns", sql(&stmt), elapsed.as_nanos())
+            }
+            TraceEvent::Close(_) => "-- closing database connection".to_string(),
+            _ => return,
+        };
+
+        match &mut tracer.sink {
+            Sink::Output(pending) => pending.push(line),
+            Sink::Writer(writer) => {
+                let _ = writeln!(writer, "{}", line);
+                let _ = writer.flush();
+            }
+        }
+    });
+}
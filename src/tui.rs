@@ -7,11 +7,13 @@ use rustyline::completion::Completer;
 use rustyline::config::Configurer;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
-use rustyline::validate::Validator;
-use rustyline::{CompletionType, Config, EditMode, Editor, history::FileHistory};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{
+    Cmd, CompletionType, ConditionalEventHandler, Config, EditMode, Editor, Event, EventContext,
+    EventHandler, KeyCode, KeyEvent, Modifiers, RepeatCount, history::FileHistory,
+};
 use std::borrow::Cow;
 use std::cell::RefCell;
-use std::hint::spin_loop;
 use std::path::PathBuf;
 use std::rc::Rc;
 
@@ -45,7 +47,7 @@ impl<'a> Completer for PromptCompleter<'a> {
         &self, // FIXME should be `&mut self`
         line: &str,
         pos: usize,
-        ctx: &rustyline::Context<'_>,
+        _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
         let tokens = util::tokenize(line);
 
@@ -57,7 +59,7 @@ impl<'a> Completer for PromptCompleter<'a> {
                 .prepare(get_tables_sql)
                 .expect("unable to create a prepared statement");
             let col_count = stmt.column_count();
-            let tables = util::query_data_rows(&mut stmt, col_count, TableMode::Box, None)
+            let tables = util::query_data_rows(&mut stmt, (), col_count, TableMode::Box, None)
                 .expect("unable to query tables");
             let tables: Vec<String> = tables.into_iter().flatten().collect();
             table_names.extend(tables);
@@ -80,18 +82,22 @@ impl<'a> Completer for PromptCompleter<'a> {
 const GRUVBOX_RED: &str = "\x1b[38;5;167m"; // Keywords
 const GRUVBOX_GREEN: &str = "\x1b[38;5;142m"; // Strings
 const GRUVBOX_YELLOW: &str = "\x1b[38;5;214m"; // Functions
-const GRUVBOX_BLUE: &str = "\x1b[38;5;109m"; // Numbers
 const GRUVBOX_PURPLE: &str = "\x1b[38;5;175m"; // Types
 const GRUVBOX_AQUA: &str = "\x1b[38;5;108m"; // Operators
 const GRUVBOX_ORANGE: &str = "\x1b[38;5;208m"; // Built-in functions
 const RESET: &str = "\x1b[0m";
 
 impl<'a> Highlighter for PromptCompleter<'a> {
-    fn highlight_char(&self, line: &str, pos: usize, kind: rustyline::highlight::CmdKind) -> bool {
+    fn highlight_char(
+        &self,
+        _line: &str,
+        _pos: usize,
+        _kind: rustyline::highlight::CmdKind,
+    ) -> bool {
         true
     }
 
-    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
         let mut result = String::new();
         let mut chars = line.chars().peekable();
         let mut current_word = String::new();
@@ -125,11 +131,11 @@ impl<'a> Highlighter for PromptCompleter<'a> {
                         if next_ch == '\'' {
                             break;
                         }
-                        if next_ch == '\\' {
-                            if let Some(&escaped) = chars.peek() {
-                                chars.next();
-                                result.push(escaped);
-                            }
+                        if next_ch == '\\'
+                            && let Some(&escaped) = chars.peek()
+                        {
+                            chars.next();
+                            result.push(escaped);
                         }
                     }
                     result.push_str(RESET);
@@ -200,13 +206,56 @@ impl<'a> Highlighter for PromptCompleter<'a> {
             }
         }
 
-        Cow::Owned(result)
+        // the indentation of continuation lines is where their prompt goes
+        let prompt = format!("\n{}", CONTINUATION_PROMPT);
+        Cow::Owned(result.replace(&continuation_indent(), &prompt))
     }
 }
 impl<'a> Hinter for PromptCompleter<'a> {
     type Hint = &'static str;
 }
-impl<'a> Validator for PromptCompleter<'a> {}
+impl<'a> Validator for PromptCompleter<'a> {
+    // the whole statement ends up in a single history entry
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if is_complete_input(ctx.input()) {
+            Ok(ValidationResult::Valid(None))
+        } else {
+            Ok(ValidationResult::Incomplete)
+        }
+    }
+}
+
+const PROMPT: &str = "shqlite> ";
+const CONTINUATION_PROMPT: &str = "    ...> ";
+
+// rustyline places the cursor by the width of the buffer, so a new line of an unfinished
+// statement starts with spaces as wide as the continuation prompt, the highlighter draws
+// the prompt over them
+struct ContinueStatement;
+
+fn continuation_indent() -> String {
+    format!("\n{}", " ".repeat(CONTINUATION_PROMPT.len()))
+}
+
+impl ConditionalEventHandler for ContinueStatement {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if is_complete_input(ctx.line()) {
+            None
+        } else {
+            let mut indent = continuation_indent();
+            // vi mode leaves the cursor on the last inserted character
+            if ctx.mode() == EditMode::Vi {
+                indent.push(' ');
+            }
+            Some(Cmd::Insert(1, indent))
+        }
+    }
+}
+
+fn is_complete_input(input: &str) -> bool {
+    let trimmed = input.trim_start();
+    trimmed.is_empty() || trimmed.starts_with('.') || util::is_complete_sql(input)
+}
 
 pub struct Prompt<'a> {
     editor: Editor<PromptCompleter<'a>, FileHistory>,
@@ -228,6 +277,10 @@ impl<'a> Prompt<'a> {
         let completer = PromptCompleter::new(conn);
         editor.set_helper(Some(completer));
         editor.set_completion_type(CompletionType::List);
+        editor.bind_sequence(
+            KeyEvent(KeyCode::Enter, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(ContinueStatement)),
+        );
 
         let mut hist_file = std::env::home_dir().unwrap_or(PathBuf::from(".shqlite_history"));
         hist_file.push(".shqlite_history");
@@ -244,6 +297,7 @@ impl<'a> Prompt<'a> {
     }
 
     pub fn readline(&mut self) -> rustyline::Result<String> {
-        self.editor.readline("shqlite> ")
+        let input = self.editor.readline(PROMPT)?;
+        Ok(input.replace(&continuation_indent(), "\n"))
     }
 }
@@ -1,12 +1,19 @@
-use crate::config::TableMode;
+use crate::columnar::ColumnLayout;
+use crate::config::{ColumnarOptions, Separators, TableMode};
 use prettytable::format::Alignment;
 use prettytable::format::TableFormat;
 use prettytable::{Cell, Row, Table};
-use rusqlite::Statement;
 use rusqlite::backup::Progress;
+use rusqlite::config::DbConfig;
+use rusqlite::types::Value;
+use rusqlite::{Connection, Params, Statement, ffi};
 use rustyline::error::ReadlineError;
+use std::ffi::{CString, c_char};
+use std::fmt;
 use std::io::Write;
 use std::process::exit;
+use std::ptr;
+use std::time::{Duration, Instant};
 
 use rusqlite::types::ValueRef;
 
@@ -25,6 +32,7 @@ pub fn should_complete_tables(tokens: &[&str]) -> bool {
     matches!(prev_word, "JOIN" | "FROM")
 }
 
+#[allow(dead_code)]
 pub fn should_complete_columns(tokens: &[&str]) -> bool {
     if tokens.len() < 2 {
         return false;
@@ -41,7 +49,7 @@ pub fn show_progress(prog: Progress) {
     let filled = (bar_width * completed) / prog.pagecount;
 
     let bar: String = (0..bar_width)
-        .map(|i| if i < filled as i32 { '█' } else { '░' })
+        .map(|i| if i < filled { '█' } else { '░' })
         .collect();
 
     print!(
@@ -51,16 +59,108 @@ pub fn show_progress(prog: Progress) {
     std::io::stdout().flush().unwrap();
 }
 
-pub fn handle_readline_err(err: ReadlineError) {
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timing {
+    pub real: Duration,
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+impl Timing {
+    pub fn add(&mut self, other: Timing) {
+        self.real += other.real;
+        self.user += other.user;
+        self.sys += other.sys;
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "real {:.3} user {:.6} sys {:.6}",
+            self.real.as_secs_f64(),
+            self.user.as_secs_f64(),
+            self.sys.as_secs_f64()
+        )
+    }
+}
+
+pub struct Stopwatch {
+    start: Instant,
+    user: Duration,
+    sys: Duration,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        let (user, sys) = cpu_times();
+        Self {
+            start: Instant::now(),
+            user,
+            sys,
+        }
+    }
+
+    pub fn elapsed(&self) -> Timing {
+        let (user, sys) = cpu_times();
+        Timing {
+            real: self.start.elapsed(),
+            user: user.saturating_sub(self.user),
+            sys: sys.saturating_sub(self.sys),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn cpu_times() -> (Duration, Duration) {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: `getrusage` fully initializes `usage` when it returns 0
+    let usage = unsafe {
+        if libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) != 0 {
+            return (Duration::ZERO, Duration::ZERO);
+        }
+        usage.assume_init()
+    };
+
+    let to_duration = |tv: libc::timeval| {
+        Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+    };
+    (to_duration(usage.ru_utime), to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_times() -> (Duration, Duration) {
+    (Duration::ZERO, Duration::ZERO)
+}
+
+#[cfg(unix)]
+pub fn terminal_width() -> Option<usize> {
+    let mut size = std::mem::MaybeUninit::<libc::winsize>::uninit();
+    // SAFETY: `TIOCGWINSZ` fills in `size` when it returns 0
+    let size = unsafe {
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) != 0 {
+            return None;
+        }
+        size.assume_init()
+    };
+
+    (size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+#[cfg(not(unix))]
+pub fn terminal_width() -> Option<usize> {
+    None
+}
+
+pub fn handle_readline_err(err: ReadlineError) -> bool {
     match err {
         ReadlineError::Eof => {
             println!("Ctrl-D Bye!");
-            exit(0)
-        }
-        ReadlineError::Interrupted => {
-            println!("Ctrl-C Bye!");
-            exit(0)
+            false
         }
+        // Ctrl-C at the prompt only discards the line that is being typed
+        ReadlineError::Interrupted => true,
         _ => {
             println!("ERROR: {}", err);
             exit(1)
@@ -68,6 +168,181 @@ pub fn handle_readline_err(err: ReadlineError) {
     }
 }
 
+pub fn is_interrupted(err: &rusqlite::Error) -> bool {
+    err.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted)
+}
+
+// the same test the `sqlite3` shell uses to decide that it has read enough input
+pub fn is_complete_sql(sql: &str) -> bool {
+    let Ok(sql) = CString::new(sql) else {
+        return false;
+    };
+    // SAFETY: `sql` is a NUL-terminated CString that lives until the end of this function,
+    // `sqlite3_complete` only reads it during the call
+    unsafe { ffi::sqlite3_complete(sql.as_ptr()) != 0 }
+}
+
+// a `;` only ends a statement when the text before it is complete, whatever follows the
+// last complete statement is returned as the last statement
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+
+    for (idx, _) in sql.match_indices(';') {
+        let statement = &sql[start..=idx];
+        if is_complete_sql(statement) {
+            statements.push(statement);
+            start = idx + 1;
+        }
+    }
+    statements.push(&sql[start..]);
+
+    statements
+        .into_iter()
+        .map(str::trim)
+        .filter(|statement| !statement.trim_end_matches(';').trim().is_empty())
+        .collect()
+}
+
+pub fn quote_identifier(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+pub fn quote_identifier_if_needed(ident: &str) -> String {
+    let is_plain = ident
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && ident.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if is_plain && !crate::consts::is_sqlite_keyword(&ident.to_uppercase()) {
+        ident.to_string()
+    } else {
+        quote_identifier(ident)
+    }
+}
+
+pub fn quote_string_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+// unless `keep_newlines` is set, line breaks are written as `char(10)` and `char(13)`
+pub fn sql_literal(value: ValueRef, keep_newlines: bool) -> String {
+    match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(fp) if fp.is_nan() => "NULL".to_string(),
+        ValueRef::Real(fp) if fp.is_infinite() => if fp.is_sign_positive() {
+            "1e999"
+        } else {
+            "-1e999"
+        }
+        .to_string(),
+        // the debug representation is the shortest one that round trips
+        ValueRef::Real(fp) => format!("{:?}", fp),
+        ValueRef::Text(txt) => {
+            let txt = String::from_utf8_lossy(txt);
+            if keep_newlines || !txt.contains(['\n', '\r']) {
+                return quote_string_literal(&txt);
+            }
+            escaped_text_literal(&txt)
+        }
+        ValueRef::Blob(blob) => {
+            let hex: String = blob.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("X'{}'", hex)
+        }
+    }
+}
+
+// picks escape markers that don't already appear in the text
+fn escaped_text_literal(txt: &str) -> String {
+    let mut literal = txt.to_string();
+    let mut replacements = Vec::new();
+
+    for (ch, code, markers) in [
+        ('\n', 10, ["\\n", "\\012", "\\LF"]),
+        ('\r', 13, ["\\r", "\\015", "\\CR"]),
+    ] {
+        if !literal.contains(ch) {
+            continue;
+        }
+        // keep the raw character if every marker is already part of the text
+        if let Some(marker) = markers.into_iter().find(|marker| !txt.contains(marker)) {
+            literal = literal.replace(ch, marker);
+            replacements.push((marker, code));
+        }
+    }
+
+    let mut result = quote_string_literal(&literal);
+    for (marker, code) in replacements {
+        result = format!("replace({},'{}',char({}))", result, marker, code);
+    }
+    result
+}
+
+pub fn escape(input: &str) -> String {
+    let mut result = String::with_capacity(input.len());
+
+    for ch in input.chars() {
+        match ch {
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\0' => result.push_str("\\0"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            _ if ch.is_control() => result.push_str(&format!("\\x{:02X}", ch as u32)),
+            _ => result.push(ch),
+        }
+    }
+
+    result
+}
+
+pub fn strip_quotes(arg: &str) -> &str {
+    ['\'', '"']
+        .iter()
+        .find_map(|&quote| arg.strip_prefix(quote)?.strip_suffix(quote))
+        .unwrap_or(arg)
+}
+
+// `\xHH` is limited to ASCII, larger values aren't a character on their own in UTF-8
+pub fn unescape(input: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(input.len());
+    let mut chars = input.chars();
+
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('x') => {
+                let hex: String = chars.clone().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && byte.is_ascii() => {
+                        result.push(byte as char);
+                        chars.nth(1);
+                    }
+                    Ok(_) if hex.len() == 2 => {
+                        return Err(format!("\\x{} is not an ASCII character", hex));
+                    }
+                    _ => result.push_str("\\x"),
+                }
+            }
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+
+    Ok(result)
+}
+
 pub fn on_or_off_to_bool(on_or_off: &str) -> bool {
     match on_or_off {
         "on" => true,
@@ -92,18 +367,54 @@ pub fn construct_and_print_output(
     title: Vec<String>,
     data: Vec<Vec<String>>,
     with_header: bool,
+    columnar: &ColumnarOptions,
+    separators: &Separators,
 ) {
     match mode {
         // these modes can't be constructed using the `prettytable` crate
-        TableMode::Ascii => print_ugly_ascii(writer, data, title, with_header),
+        TableMode::List | TableMode::Csv | TableMode::Tabs | TableMode::Ascii => {
+            let title = with_header.then_some(title);
+            print_delimited(
+                writer,
+                title,
+                data,
+                separators,
+                matches!(mode, TableMode::Csv),
+            )
+        }
         TableMode::Html => print_fmtted_html(writer, data, title),
         TableMode::Insert => print_insert(writer, data, title),
         TableMode::Json => print_fmtted_json(writer, data, title),
         TableMode::Line => print_line_mode(writer, data, title),
         // else `prettytable` is able to print. even though `prettytable` could print html like
         _ => {
+            let col_count = title.len();
+            let mut alignments = vec![Alignment::LEFT; col_count];
+            let (title, data) = if mode.is_columnar() {
+                // only a table that is shown in the terminal has to fit into it
+                let max_width = match writer {
+                    Output::BufferedStdout(_) => terminal_width(),
+                    Output::BufferedFile(..) => None,
+                };
+                let layout = ColumnLayout::new(&title, &data, columnar, max_width);
+                alignments = layout.alignments.clone();
+                // a markdown cell can't span several lines
+                let truncate = matches!(mode, TableMode::Markdown);
+                layout.apply(title, data, columnar.word_wrap, truncate)
+            } else {
+                (title, data)
+            };
+
             // populate the table with the row data
-            let mut table = Table::from(data);
+            let mut table = Table::new();
+            for row in data {
+                let cells = row
+                    .iter()
+                    .zip(&alignments)
+                    .map(|(cell, alignment)| Cell::new_align(cell, *alignment))
+                    .collect();
+                table.add_row(Row::new(cells));
+            }
 
             // get the title row and append it to the first row in the table
             // this must be collected into a `Vec<Cell>` instead of `Row` because
@@ -113,17 +424,7 @@ pub fn construct_and_print_output(
                 .map(|colname| Cell::new_align(&colname, Alignment::CENTER))
                 .collect();
 
-            // appends the title based on this rule
-            match mode {
-                TableMode::List | TableMode::Csv | TableMode::Tabs => {
-                    if with_header {
-                        table.set_titles(Row::new(centered_title));
-                    } else {
-                        table.unset_titles();
-                    }
-                }
-                _ => table.set_titles(Row::new(centered_title)),
-            }
+            table.set_titles(Row::new(centered_title));
 
             // formats the table based on `mode`
             let fmt = TableFormat::try_from(mode).unwrap_or(*crate::consts::BOX);
@@ -136,6 +437,19 @@ pub fn construct_and_print_output(
     }
 }
 
+pub fn query_literal_rows(
+    stmt: &mut Statement,
+    params: impl Params,
+    col_count: usize,
+) -> rusqlite::Result<Vec<Vec<String>>> {
+    let rows = stmt.query_map(params, |row| {
+        (0..col_count)
+            .map(|col_idx| Ok(sql_literal(row.get_ref(col_idx)?, true)))
+            .collect()
+    })?;
+    rows.collect()
+}
+
 pub fn query_title_row(
     stmt: &mut Statement,
     col_count: usize,
@@ -147,7 +461,7 @@ pub fn query_title_row(
         let title = match mode {
             TableMode::Quote => format!("'{}'", stmt.column_name(col_idx)?),
             TableMode::Tcl => format!("\"{}\"", stmt.column_name(col_idx)?),
-            _ => format!("{}", stmt.column_name(col_idx)?),
+            _ => stmt.column_name(col_idx)?.to_string(),
         };
         titles.push(title);
     }
@@ -157,11 +471,12 @@ pub fn query_title_row(
 
 pub fn query_data_rows(
     stmt: &mut Statement,
+    params: impl Params,
     col_count: usize,
     mode: TableMode,
     null_value: Option<&String>,
 ) -> rusqlite::Result<Vec<Vec<String>>> {
-    let rows = stmt.query_map((), |row| {
+    let rows = stmt.query_map(params, |row| {
         let mut data = Vec::with_capacity(col_count);
         for col_idx in 0..col_count {
             let valref = row.get_ref(col_idx)?;
@@ -173,6 +488,49 @@ pub fn query_data_rows(
     rows.collect()
 }
 
+// parameters without a binding, including anonymous `?`, are NULL
+pub fn lookup_parameters(conn: &Connection, stmt: &Statement) -> rusqlite::Result<Vec<Value>> {
+    let param_count = stmt.parameter_count();
+    let mut values = vec![Value::Null; param_count];
+    if param_count == 0 || !parameter_table_exists(conn)? {
+        return Ok(values);
+    }
+
+    let mut lookup_stmt =
+        conn.prepare_cached("SELECT value FROM temp.sqlite_parameters WHERE key = ?1")?;
+    for (idx, value) in values.iter_mut().enumerate() {
+        // parameters are numbered from 1
+        if let Some(name) = stmt.parameter_name(idx + 1) {
+            let mut rows = lookup_stmt.query([name])?;
+            if let Some(row) = rows.next()? {
+                *value = row.get(0)?;
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+// names that start with `sqlite_` are reserved, so the schema has to be writable for this
+pub fn init_parameter_table(conn: &Connection) -> rusqlite::Result<()> {
+    let sql = "CREATE TABLE IF NOT EXISTS temp.sqlite_parameters(key TEXT PRIMARY KEY, value) WITHOUT ROWID";
+    let was_defensive = conn.db_config(DbConfig::SQLITE_DBCONFIG_DEFENSIVE)?;
+    let was_writable = conn.db_config(DbConfig::SQLITE_DBCONFIG_WRITABLE_SCHEMA)?;
+
+    conn.set_db_config(DbConfig::SQLITE_DBCONFIG_DEFENSIVE, false)?;
+    conn.set_db_config(DbConfig::SQLITE_DBCONFIG_WRITABLE_SCHEMA, true)?;
+    let result = conn.execute(sql, ());
+    conn.set_db_config(DbConfig::SQLITE_DBCONFIG_WRITABLE_SCHEMA, was_writable)?;
+    conn.set_db_config(DbConfig::SQLITE_DBCONFIG_DEFENSIVE, was_defensive)?;
+
+    result.map(|_| ())
+}
+
+pub fn parameter_table_exists(conn: &Connection) -> rusqlite::Result<bool> {
+    let sql = "SELECT count(*) FROM temp.sqlite_schema WHERE name = 'sqlite_parameters'";
+    conn.query_row(sql, (), |row| row.get(0))
+}
+
 fn parse_sql_value(sql_val: ValueRef, mode: TableMode, null_value: Option<&String>) -> String {
     let null_value = null_value.cloned().unwrap_or(String::new());
     match sql_val {
@@ -194,7 +552,7 @@ fn print_fmtted_json(writer: &mut Output, data: Vec<Vec<String>>, title: Vec<Str
     // cast this into a trait object to reduce duplicate code
     let writer: &mut dyn Write = match writer {
         Output::BufferedStdout(out) => out,
-        Output::BufferedFile(f) => f,
+        Output::BufferedFile(f, _) => f,
     };
 
     // the length of all data rows
@@ -205,25 +563,17 @@ fn print_fmtted_json(writer: &mut Output, data: Vec<Vec<String>>, title: Vec<Str
     // always start with an array of json
     let _ = writeln!(writer, "[");
     // print each data row
-    for data_idx in 0..total_data_rows {
+    for (data_idx, row) in data.iter().enumerate() {
         // three spaces for json object
         let _ = writeln!(writer, "   {{");
         // for each data row, print each cells with its title
         // it is guaranteed that the length of each data row is equal to the length of title
         for cell_idx in 0..total_cells {
             // five spaces for json fields
-            let _ = writeln!(
-                writer,
-                "     \"{}\": {},",
-                title[cell_idx], data[data_idx][cell_idx]
-            );
+            let _ = writeln!(writer, "     \"{}\": {},", title[cell_idx], row[cell_idx]);
             // if we are at the last field, we omit the last comma for a valid json object
             if cell_idx == total_cells - 1 {
-                let _ = writeln!(
-                    writer,
-                    "     \"{}\": {}",
-                    title[cell_idx], data[data_idx][cell_idx]
-                );
+                let _ = writeln!(writer, "     \"{}\": {}", title[cell_idx], row[cell_idx]);
                 continue;
             }
         }
@@ -242,29 +592,24 @@ fn print_fmtted_html(writer: &mut Output, data: Vec<Vec<String>>, title: Vec<Str
     // cast this into a trait object to reduce duplicate code
     let writer: &mut dyn Write = match writer {
         Output::BufferedStdout(out) => out,
-        Output::BufferedFile(f) => f,
+        Output::BufferedFile(f, _) => f,
     };
 
-    // the length of all data rows
-    let total_data_rows = data.len();
-    // total cells of each rows (title and data)
-    let total_cells = title.len();
-
     // print the headers first which contains the title name for each data
     let _ = writeln!(writer, "<tr>");
 
-    for title_cell_idx in 0..total_cells {
-        let _ = writeln!(writer, "    <th>{}</th>", title[title_cell_idx]);
+    for name in &title {
+        let _ = writeln!(writer, "    <th>{}</th>", name);
     }
 
     let _ = writeln!(writer, "</tr>");
 
     // print the content that matches the "schema" that the header
 
-    for data_idx in 0..total_data_rows {
+    for row in &data {
         let _ = writeln!(writer, "<tr>");
-        for cell_idx in 0..total_cells {
-            let _ = writeln!(writer, "    <td>{}</td>", data[data_idx][cell_idx]);
+        for value in row {
+            let _ = writeln!(writer, "    <td>{}</td>", value);
         }
         let _ = writeln!(writer, "</tr>");
     }
@@ -276,7 +621,7 @@ fn print_insert(writer: &mut Output, datas: Vec<Vec<String>>, title: Vec<String>
     // cast this into a trait object to reduce duplicate code
     let writer: &mut dyn Write = match writer {
         Output::BufferedStdout(out) => out,
-        Output::BufferedFile(f) => f,
+        Output::BufferedFile(f, _) => f,
     };
 
     for data in datas {
@@ -292,32 +637,38 @@ fn print_insert(writer: &mut Output, datas: Vec<Vec<String>>, title: Vec<String>
     writer.flush().expect("unable to flush");
 }
 
-fn print_ugly_ascii(
+// with `csv_quoting`, values are quoted like RFC 4180 asks for
+fn print_delimited(
     writer: &mut Output,
+    title: Option<Vec<String>>,
     datas: Vec<Vec<String>>,
-    title: Vec<String>,
-    with_header: bool,
+    separators: &Separators,
+    csv_quoting: bool,
 ) {
     // cast this into a trait object to reduce duplicate code
     let writer: &mut dyn Write = match writer {
         Output::BufferedStdout(out) => out,
-        Output::BufferedFile(f) => f,
+        Output::BufferedFile(f, _) => f,
     };
 
-    // default capacity
-    let mut final_string = String::with_capacity(2048);
-
-    if with_header {
-        let joined_title = title.join("");
-        final_string.push_str(&joined_title);
-    }
+    let column_sep = &separators.column;
+    let row_sep = &separators.row;
+    let quote = |value: &String| {
+        let needs_quotes = csv_quoting
+            && (value.contains(['"', '\n', '\r'])
+                || value.contains(column_sep.as_str())
+                || value.contains(row_sep.as_str()));
+        if needs_quotes {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.clone()
+        }
+    };
 
-    for data in datas {
-        let joined = data.join("");
-        final_string.push_str(&joined);
+    for row in title.into_iter().chain(datas) {
+        let fields: Vec<String> = row.iter().map(quote).collect();
+        let _ = write!(writer, "{}{}", fields.join(column_sep), row_sep);
     }
-
-    let _ = writeln!(writer, "{}", final_string);
     writer.flush().expect("unable flush");
 }
 
@@ -325,7 +676,7 @@ fn print_line_mode(writer: &mut Output, datas: Vec<Vec<String>>, title: Vec<Stri
     // cast this into a trait object to reduce duplicate code
     let writer: &mut dyn Write = match writer {
         Output::BufferedStdout(out) => out,
-        Output::BufferedFile(f) => f,
+        Output::BufferedFile(f, _) => f,
     };
 
     let max_len = title.iter().map(|entry| entry.len()).max().unwrap_or(0);
@@ -338,3 +689,109 @@ fn print_line_mode(writer: &mut Output, datas: Vec<Vec<String>>, title: Vec<Stri
         }
     }
 }
+
+pub fn drop_module(conn: &Connection, name: &str) -> Result<(), String> {
+    let name = CString::new(name).map_err(|e| e.to_string())?;
+    // SAFETY: `conn.handle()` is the open connection that `conn` owns. `name` is
+    // NUL-terminated and outlives the call, SQLite copies it. A NULL module with no client
+    // data and no destructor is how the module is removed
+    let rc = unsafe {
+        ffi::sqlite3_create_module_v2(
+            conn.handle(),
+            name.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            None,
+        )
+    };
+    if rc != ffi::SQLITE_OK {
+        return Err(format!("unable to unregister module, error code {}", rc));
+    }
+
+    Ok(())
+}
+
+pub fn drop_modules_except(conn: &Connection, keep: &[&str]) -> Result<(), String> {
+    let keep = keep
+        .iter()
+        .map(|name| CString::new(*name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    // the list of names that is passed to SQLite is terminated by a null pointer
+    let mut names: Vec<*const c_char> = keep.iter().map(|name| name.as_ptr()).collect();
+    names.push(ptr::null());
+
+    // SAFETY: `conn.handle()` is the open connection that `conn` owns. `names` is
+    // NULL-terminated and points into the CStrings of `keep`. Both outlive the call, and
+    // SQLite only reads the names while the call runs
+    let rc = unsafe { ffi::sqlite3_drop_modules(conn.handle(), names.as_mut_ptr()) };
+    if rc != ffi::SQLITE_OK {
+        return Err(format!("unable to unregister modules, error code {}", rc));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_statements_keeps_semicolons_that_dont_end_a_statement() {
+        let sql = "SELECT ';'; -- a; comment\nSELECT 2;\n\
+                   CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT 1; SELECT 2; END;";
+        assert_eq!(
+            split_statements(sql),
+            [
+                "SELECT ';';",
+                "-- a; comment\nSELECT 2;",
+                "CREATE TRIGGER t AFTER INSERT ON a BEGIN SELECT 1; SELECT 2; END;",
+            ]
+        );
+    }
+
+    #[test]
+    fn split_statements_returns_an_unfinished_statement_last() {
+        assert_eq!(
+            split_statements("SELECT 1; SELECT 2"),
+            ["SELECT 1;", "SELECT 2"]
+        );
+        assert_eq!(split_statements("SELECT 'a;"), ["SELECT 'a;"]);
+    }
+
+    #[test]
+    fn split_statements_skips_empty_statements() {
+        assert_eq!(split_statements(" ;; SELECT 1;\n ; "), ["SELECT 1;"]);
+        assert!(split_statements("").is_empty());
+    }
+
+    #[test]
+    fn unescape_expands_escapes() {
+        assert_eq!(unescape(r"a\tb\nc\r\0").unwrap(), "a\tb\nc\r\0");
+        assert_eq!(unescape(r#"\\ \" \'"#).unwrap(), r#"\ " '"#);
+        assert_eq!(unescape(r"\x1f\x41").unwrap(), "\x1fA");
+    }
+
+    #[test]
+    fn unescape_keeps_incomplete_escapes() {
+        assert_eq!(unescape(r"\x4").unwrap(), r"\x4");
+        assert_eq!(unescape(r"\xzz").unwrap(), r"\xzz");
+        assert_eq!(unescape("a\\").unwrap(), "a\\");
+    }
+
+    #[test]
+    fn unescape_rejects_bytes_above_ascii() {
+        assert!(unescape(r"\x80").is_err());
+        assert!(unescape(r"\xFE").is_err());
+    }
+
+    #[test]
+    fn escape_is_undone_by_unescape() {
+        let text = "tab\there\nquote\" backslash\\ nul\0 unit\x1f ü";
+        assert_eq!(
+            escape(text),
+            r#"tab\there\nquote\" backslash\\ nul\0 unit\x1F ü"#
+        );
+        assert_eq!(unescape(&escape(text)).unwrap(), text);
+    }
+}